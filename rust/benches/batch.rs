@@ -0,0 +1,40 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use trustmark::{EncodeOptions, Trustmark, Variant, Version};
+
+const BATCH_SIZES: [usize; 3] = [1, 4, 16];
+
+fn batch_main(c: &mut Criterion) {
+    let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+    let input = image::open("../images/ufo_240.jpg").unwrap();
+    let watermark = "0100100100100001000101001010".to_owned();
+    let options = EncodeOptions::default();
+
+    let mut group = c.benchmark_group("encode_batch");
+    for size in BATCH_SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("sequential", size), &size, |b, &size| {
+            b.iter(|| {
+                for _ in 0..size {
+                    let _ = tm.encode_with_options(watermark.clone(), input.clone(), &options);
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("batched", size), &size, |b, &size| {
+            b.iter(|| {
+                let batch = (0..size).map(|_| (watermark.clone(), input.clone()));
+                let _ = tm.encode_batch(batch, &options);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batch_main);
+criterion_main!(benches);
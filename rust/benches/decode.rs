@@ -0,0 +1,98 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat};
+use trustmark::{DecodeOptions, Decoding, Trustmark, Variant, Version};
+
+/// The JPEG qualities recovery is compared at, from mild to heavy recompression.
+const QUALITIES: [u8; 4] = [60, 40, 30, 20];
+
+/// The number of watermarks recovery is compared over at each quality.
+const PAYLOADS: usize = 16;
+
+/// Recompress `img` as a JPEG of the given quality.
+fn recompress(img: &DynamicImage, quality: u8) -> DynamicImage {
+    let mut jpeg = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut jpeg, quality);
+    img.to_rgb8().write_with_encoder(encoder).unwrap();
+    image::load(Cursor::new(jpeg), ImageFormat::Jpeg).unwrap()
+}
+
+/// Print how many watermarks each decoding recovers after recompression, since Chase decoding is
+/// only worth its time if it recovers watermarks which hard decoding doesn't.
+fn recovery(tm: &Trustmark, input: &DynamicImage, decodings: &[(&str, DecodeOptions)]) {
+    let data_bits = Version::Bch5.data_bits() as usize;
+    let payloads: Vec<String> = (0..PAYLOADS)
+        .map(|i| {
+            (0..data_bits)
+                .map(|j| if (i * 7 + j * j) % 3 == 0 { '1' } else { '0' })
+                .collect()
+        })
+        .collect();
+    let encoded: Vec<_> = payloads
+        .iter()
+        .map(|payload| tm.encode(payload.clone(), input.clone(), 0.95).unwrap())
+        .collect();
+
+    for quality in QUALITIES {
+        let recompressed: Vec<_> = encoded.iter().map(|img| recompress(img, quality)).collect();
+        let rates: Vec<String> = decodings
+            .iter()
+            .map(|(name, options)| {
+                let recovered = recompressed
+                    .iter()
+                    .zip(&payloads)
+                    .filter(|(img, payload)| {
+                        tm.decode_with_options((*img).clone(), options)
+                            .is_ok_and(|report| &report.data == *payload)
+                    })
+                    .count();
+                format!("{name} {recovered}/{PAYLOADS}")
+            })
+            .collect();
+        println!("recovered at JPEG quality {quality}: {}", rates.join(", "));
+    }
+}
+
+fn decode_main(c: &mut Criterion) {
+    let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+    let input = image::open("../images/ufo_240.jpg").unwrap();
+
+    let hard = DecodeOptions::default();
+    let chase = DecodeOptions {
+        decoding: Decoding::Chase { bits: 8 },
+        ..Default::default()
+    };
+    recovery(
+        &tm,
+        &input,
+        &[("hard", hard.clone()), ("chase", chase.clone())],
+    );
+
+    // Heavily recompress the watermarked image, so that error correction has work to do.
+    let encoded = tm
+        .encode("0100100100100001000101001010".to_owned(), input, 0.95)
+        .unwrap();
+    let recompressed = recompress(&encoded, 40);
+
+    c.bench_function("decode_hard", |b| {
+        b.iter(|| {
+            let _ = tm.decode_with_options(recompressed.clone(), &hard);
+        })
+    });
+    c.bench_function("decode_chase", |b| {
+        b.iter(|| {
+            let _ = tm.decode_with_options(recompressed.clone(), &chase);
+        })
+    });
+}
+
+criterion_group!(benches, decode_main);
+criterion_main!(benches);
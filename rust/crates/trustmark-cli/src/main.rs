@@ -5,17 +5,33 @@
 // accordance with the terms of the Adobe license agreement accompanying
 // it.
 
-use std::{fs::OpenOptions, path::PathBuf};
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
 
-use clap::{Parser, Subcommand};
-use image::{codecs::jpeg::JpegEncoder, ImageFormat};
-use rand::{distributions::Standard, prelude::Distribution as _};
-use trustmark::{Trustmark, Variant, Version};
+use clap::{error::ErrorKind, CommandFactory as _, Parser, Subcommand};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, GenericImageView as _, ImageFormat};
+use rand::{
+    distributions::{Alphanumeric, Standard},
+    prelude::Distribution as _,
+};
+use trustmark::{
+    eval::{self, Attack},
+    layer, validate_aspect_ratio_limit, DecodeOptions, DecodeReport, Decoding, Dither,
+    EncodeOptions, Gamut, Mode, MultiVariantDecoder, Payload, Rect, Region, RemoveOptions, Search,
+    StrengthSearch, StrengthTarget, Tiling, Trustmark, Variant, Verify, Version, MAX_CHASE_BITS,
+};
 
 #[derive(Debug, Parser)]
 struct Args {
+    /// The directory containing the models. If not given, the models embedded in the binary
+    /// with an `embed-*` feature are used.
     #[arg(short, long)]
-    models: PathBuf,
+    models: Option<PathBuf>,
+    /// The number of threads each model uses. Defaults to 8.
+    #[arg(long)]
+    threads: Option<usize>,
     #[command(subcommand)]
     command: Command,
 }
@@ -33,6 +49,10 @@ enum Command {
         /// The watermark to encode. Defaults to random if not specified.
         #[arg(short, long)]
         watermark: Option<String>,
+        /// How to interpret the watermark: `binary`, `text`, or `bytes` (as hex). Defaults to
+        /// binary.
+        #[arg(long)]
+        mode: Option<Mode>,
         /// The BCH version to encode with. Defaults to BchSuper.
         #[arg(long)]
         version: Option<Version>,
@@ -42,14 +62,144 @@ enum Command {
         /// If the requested output is JPEG, the quality to use for encoding.
         #[arg(long)]
         quality: Option<u8>,
+        /// How strong the watermark should be, between 0 and 1. Defaults to 0.95.
+        #[arg(long, conflicts_with_all = ["target_psnr", "target_jpeg"])]
+        strength: Option<f32>,
+        /// Choose the strongest strength which keeps at least this PSNR, in decibels.
+        #[arg(long, conflicts_with = "target_jpeg")]
+        target_psnr: Option<f32>,
+        /// Choose the weakest strength at which the watermark survives JPEG compression at this
+        /// quality.
+        #[arg(long)]
+        target_jpeg: Option<u8>,
+        /// Decode the watermarked image, and raise the strength by 0.25 up to 2.0 until the
+        /// watermark reads back.
+        #[arg(long, conflicts_with_all = ["target_psnr", "target_jpeg"])]
+        verify: bool,
+        /// When verifying, compress the image as a JPEG of this quality before decoding it.
+        #[arg(long, requires = "verify")]
+        verify_jpeg: Option<u8>,
+        /// Images with an aspect ratio above this are only watermarked in a center square.
+        /// Defaults to 2.0; 1.0 always crops.
+        #[arg(long, value_parser = parse_aspect_ratio_limit)]
+        aspect_ratio_limit: Option<f32>,
+        /// Watermark images above the aspect ratio limit in as many square tiles as fit, instead
+        /// of only the center square.
+        #[arg(long)]
+        tiled: bool,
+        /// Only watermark this rectangle of the image, given as `x,y,width,height`.
+        #[arg(long, value_parser = parse_rect, conflicts_with = "mask")]
+        region: Option<Rect>,
+        /// A grayscale image scaling the watermark at each pixel, from none where it is black to
+        /// all of it where it is white.
+        #[arg(long)]
+        mask: Option<PathBuf>,
+        /// Dither the watermarked image when quantizing it back to the input's bit depth, which
+        /// keeps weak watermarks in flat areas.
+        #[arg(long)]
+        dither: bool,
+        /// Move the parts of the watermark which would push pixels past black or white into other
+        /// channels and nearby pixels, instead of clipping them.
+        #[arg(long)]
+        redistribute: bool,
+        /// Also save the watermark as a layer which `apply-residual` can composite onto the
+        /// input. An `.exr` path saves float data, and other paths, such as `.tif` or `.png`,
+        /// 16-bit data biased by 32768. Can't be combined with `--verify`, or with
+        /// `--redistribute` since layers are always clipped.
+        #[arg(long, conflicts_with_all = ["redistribute", "verify"])]
+        layer: Option<PathBuf>,
+    },
+    /// Composite a watermark layer saved by `encode --layer` onto an image
+    ApplyResidual {
+        /// The image to watermark.
+        #[arg(short)]
+        input: PathBuf,
+        /// The watermark layer, which must have the same dimensions as the image.
+        #[arg(short)]
+        layer: PathBuf,
+        /// The path to save the watermarked image.
+        #[arg(short)]
+        output: PathBuf,
+        /// If the requested output is JPEG, the quality to use for encoding.
+        #[arg(long)]
+        quality: Option<u8>,
+        /// Dither the watermarked image when quantizing it back to the input's bit depth.
+        #[arg(long)]
+        dither: bool,
+    },
+    /// Remove a watermark from an image
+    Remove {
+        /// The image to remove the watermark from.
+        #[arg(short)]
+        input: PathBuf,
+        /// The path to save the cleaned image.
+        #[arg(short)]
+        output: PathBuf,
+        /// The model variant the watermark was encoded with.
+        #[arg(long)]
+        variant: Option<Variant>,
+        /// If the requested output is JPEG, the quality to use for encoding.
+        #[arg(long)]
+        quality: Option<u8>,
+        /// The aspect ratio limit the watermark was encoded with. Defaults to 2.0.
+        #[arg(long, value_parser = parse_aspect_ratio_limit)]
+        aspect_ratio_limit: Option<f32>,
     },
     /// Decode a watermark from an image
     Decode {
         #[arg(short)]
         input: PathBuf,
         /// The model variant to decode with.
-        #[arg(long)]
+        #[arg(long, conflicts_with = "detect")]
         variant: Option<Variant>,
+        /// Try the decoders of every variant, and report which variant found the watermark.
+        #[arg(long)]
+        detect: bool,
+        /// How to interpret the decoded watermark: `binary`, `text`, or `bytes` (as hex). Defaults
+        /// to binary.
+        #[arg(long)]
+        mode: Option<Mode>,
+        /// Use soft-decision decoding, flipping up to this many of the least confident bits. At
+        /// most 16.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(..=i64::from(MAX_CHASE_BITS)))]
+        chase_bits: Option<u8>,
+        /// The aspect ratio limit the watermark was encoded with. Defaults to 2.0.
+        #[arg(long, value_parser = parse_aspect_ratio_limit)]
+        aspect_ratio_limit: Option<f32>,
+        /// Look for a watermark encoded with `--tiled` across the whole image.
+        #[arg(long)]
+        tiled: bool,
+        /// If no watermark is found, search for one in crops, rescalings and flips of the image.
+        #[arg(long)]
+        search: bool,
+        /// Only decode this rectangle of the image, given as `x,y,width,height`.
+        #[arg(long, value_parser = parse_rect)]
+        region: Option<Rect>,
+    },
+    /// Measure how well watermarks survive common edits
+    Eval {
+        /// The images to watermark, or directories of them.
+        #[arg(short, required = true, num_args = 1..)]
+        input: Vec<PathBuf>,
+        /// The path to write the results to. Defaults to standard output.
+        #[arg(short)]
+        output: Option<PathBuf>,
+        /// A model variant to evaluate, which can be repeated. Defaults to Q.
+        #[arg(long = "variant")]
+        variants: Vec<Variant>,
+        /// A BCH version to evaluate, which can be repeated. Defaults to BCH_5.
+        #[arg(long = "version")]
+        versions: Vec<Version>,
+        /// An attack to apply, such as `jpeg:75` or `rotate:5`, which can be repeated. Defaults
+        /// to a suite of JPEG compression, resizing, cropping, blurring, noise and rotation.
+        #[arg(long = "attack")]
+        attacks: Vec<Attack>,
+        /// How strong the watermark should be, between 0 and 1. Defaults to 0.95.
+        #[arg(long)]
+        strength: Option<f32>,
+        /// Write the results as JSON instead of CSV. Implied by an output path ending in `.json`.
+        #[arg(long)]
+        json: bool,
     },
 }
 
@@ -72,6 +222,10 @@ impl Command {
                 variant: Some(variant),
                 ..
             } => *variant,
+            Command::Remove {
+                variant: Some(variant),
+                ..
+            } => *variant,
             Command::Decode {
                 variant: Some(variant),
                 ..
@@ -98,58 +252,485 @@ fn gen_watermark(bits: usize) -> String {
         .collect()
 }
 
+/// Generate a random alphanumeric text watermark which fits in as many bits as specified by
+/// `bits`.
+fn gen_text_watermark(bits: usize) -> String {
+    let rng = rand::thread_rng();
+    Alphanumeric
+        .sample_iter(rng)
+        .take(bits / 7)
+        .map(char::from)
+        .collect()
+}
+
+/// Parse a hex string such as `deadbeef` into bytes.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits = hex
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .ok_or_else(|| format!("invalid hex digit {c:?}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if digits.len() % 2 != 0 {
+        return Err("expected an even number of hex digits".to_owned());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4 | pair[1]) as u8)
+        .collect())
+}
+
+/// Parse an aspect ratio limit, checked as the library checks it.
+fn parse_aspect_ratio_limit(limit: &str) -> Result<f32, String> {
+    let limit = limit.parse::<f32>().map_err(|err| err.to_string())?;
+    validate_aspect_ratio_limit(limit).map_err(|err| err.to_string())?;
+    Ok(limit)
+}
+
+/// Parse a rectangle given as `x,y,width,height`.
+fn parse_rect(rect: &str) -> Result<Rect, String> {
+    let parts = rect
+        .split(',')
+        .map(|part| part.trim().parse::<u32>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x, y, width, height] = parts[..] else {
+        return Err("expected x,y,width,height".to_owned());
+    };
+    Ok(Rect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+/// Save `img` to `output`, in the format implied by its extension.
+///
+/// `quality` is the quality to use if the output is JPEG.
+fn save(img: &DynamicImage, output: &Path, quality: Option<u8>) {
+    let format = ImageFormat::from_path(output).unwrap();
+    match format {
+        // JPEG encoding can make visual artifacts worse, so we encode with a higher quality than
+        // the default (or the quality requested by the user).
+        ImageFormat::Jpeg => {
+            let quality = quality.unwrap_or(90);
+            let mut writer = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output)
+                .unwrap();
+            let encoder = JpegEncoder::new_with_quality(&mut writer, quality);
+            img.to_rgb8().write_with_encoder(encoder).unwrap();
+        }
+        // Keep the bit depth and color type of the input where the format supports it, such as 16
+        // bits per channel in PNG, and fall back to 8 bits per channel otherwise.
+        _ => {
+            if img.save(output).is_err() {
+                img.to_rgba8().save(output).unwrap();
+            }
+        }
+    }
+}
+
+/// Print the result of decoding a watermark.
+fn print_decoded(result: Result<DecodeReport, trustmark::Error>, mode: Mode, detect: bool) {
+    match result {
+        Ok(report) => {
+            if detect {
+                println!(
+                    "Found watermark: {} (variant {})",
+                    report.payload(mode),
+                    report.variant
+                );
+            } else {
+                println!("Found watermark: {}", report.payload(mode));
+            }
+            if let Some(transform) = report.transform {
+                println!(
+                    "Found in the {}x{} window at ({}, {}){}",
+                    transform.width,
+                    transform.height,
+                    transform.x,
+                    transform.y,
+                    if transform.flipped { ", flipped" } else { "" }
+                );
+            }
+        }
+        Err(trustmark::Error::CorruptWatermark) => {
+            println!("Corrupt or missing watermark")
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+/// Decode with the decoders of every variant.
+fn detect(models: Option<&Path>, threads: Option<usize>, command: Command) {
+    let Command::Decode {
+        input,
+        mode,
+        chase_bits,
+        aspect_ratio_limit,
+        tiled,
+        search,
+        region,
+        ..
+    } = command
+    else {
+        unreachable!("only decode supports --detect");
+    };
+    let decoder = match models {
+        Some(models) => {
+            let decoders = Variant::ALL
+                .iter()
+                .map(|&variant| {
+                    let mut builder = Trustmark::builder(variant, Version::Bch5).encoder(false);
+                    if let Some(threads) = threads {
+                        builder = builder.intra_threads(threads);
+                    }
+                    builder.build(models).unwrap()
+                })
+                .collect();
+            MultiVariantDecoder::from_decoders(decoders)
+        }
+        None => MultiVariantDecoder::embedded().unwrap(),
+    };
+    let input = image::open(input).unwrap();
+    let options = decode_options(chase_bits, aspect_ratio_limit, tiled, search, region);
+    print_decoded(
+        decoder.decode_with_options(input, &options),
+        mode.unwrap_or_default(),
+        true,
+    );
+}
+
+/// Evaluate the robustness of every variant and version requested.
+fn eval(models: Option<&Path>, threads: Option<usize>, command: Command) {
+    let Command::Eval {
+        input,
+        output,
+        mut variants,
+        mut versions,
+        mut attacks,
+        strength,
+        json,
+    } = command
+    else {
+        unreachable!("only eval is evaluated");
+    };
+    if variants.is_empty() {
+        variants.push(Variant::Q);
+    }
+    if versions.is_empty() {
+        versions.push(Version::Bch5);
+    }
+    if attacks.is_empty() {
+        attacks = Attack::suite();
+    }
+    let paths: Vec<PathBuf> = input
+        .into_iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                let mut paths: Vec<_> = std::fs::read_dir(path)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .filter(|path| ImageFormat::from_path(path).is_ok())
+                    .collect();
+                paths.sort();
+                paths
+            } else {
+                vec![path]
+            }
+        })
+        .collect();
+    let options = EncodeOptions {
+        strength: Some(strength.unwrap_or(0.95)),
+        ..Default::default()
+    };
+
+    let mut evaluations = Vec::new();
+    for &variant in &variants {
+        for &version in &versions {
+            let mut builder = Trustmark::builder(variant, version);
+            if let Some(threads) = threads {
+                builder = builder.intra_threads(threads);
+            }
+            let tm = match models {
+                Some(models) => builder.build(models),
+                None => builder.build_embedded(),
+            }
+            .unwrap();
+            let images = paths.iter().map(|path| image::open(path).unwrap());
+            evaluations.extend(eval::evaluate(&tm, images, &attacks, &options).unwrap());
+        }
+    }
+
+    let json = json
+        || output
+            .as_ref()
+            .is_some_and(|output| output.extension().is_some_and(|ext| ext == "json"));
+    let results = if json {
+        eval::to_json(&evaluations)
+    } else {
+        eval::to_csv(&evaluations)
+    };
+    match output {
+        Some(output) => std::fs::write(output, results).unwrap(),
+        None => print!("{results}"),
+    }
+}
+
+/// Composite a watermark layer onto an image.
+fn apply_residual(command: Command) {
+    let Command::ApplyResidual {
+        input,
+        layer,
+        output,
+        quality,
+        dither,
+    } = command
+    else {
+        unreachable!("only called for apply-residual");
+    };
+    let input = image::open(input).unwrap();
+    let layer = image::open(layer).unwrap();
+    let encoded = layer::apply(
+        input,
+        &layer,
+        if dither {
+            Dither::FloydSteinberg
+        } else {
+            Dither::None
+        },
+    )
+    .unwrap();
+    save(&encoded, &output, quality);
+}
+
+/// The format to save a watermark layer in at `path`.
+fn layer_format(path: &Path) -> layer::Format {
+    match ImageFormat::from_path(path) {
+        Ok(ImageFormat::OpenExr) => layer::Format::Float,
+        _ => layer::Format::Biased16,
+    }
+}
+
+fn gamut(redistribute: bool) -> Gamut {
+    if redistribute {
+        Gamut::Redistribute
+    } else {
+        Gamut::Clip
+    }
+}
+
+/// Build the decode options from the command line arguments.
+fn decode_options(
+    chase_bits: Option<u8>,
+    aspect_ratio_limit: Option<f32>,
+    tiled: bool,
+    search: bool,
+    region: Option<Rect>,
+) -> DecodeOptions {
+    let mut options = DecodeOptions {
+        decoding: match chase_bits {
+            Some(bits) => Decoding::Chase { bits },
+            None => Decoding::Hard,
+        },
+        tiling: tiling(tiled),
+        search: search.then(Search::default),
+        region,
+        ..Default::default()
+    };
+    if let Some(limit) = aspect_ratio_limit {
+        options.aspect_ratio_limit = limit;
+    }
+    options
+}
+
+/// The tiling requested by the `--tiled` flag.
+fn tiling(tiled: bool) -> Tiling {
+    if tiled {
+        Tiling::Tiled
+    } else {
+        Tiling::Center
+    }
+}
+
 fn main() {
     let args = Args::parse();
-    let tm = Trustmark::new(
-        &args.models,
-        args.command.get_variant(),
-        args.command.get_version(),
-    )
+    if matches!(args.command, Command::Decode { detect: true, .. }) {
+        detect(args.models.as_deref(), args.threads, args.command);
+        return;
+    }
+    if matches!(args.command, Command::Eval { .. }) {
+        eval(args.models.as_deref(), args.threads, args.command);
+        return;
+    }
+    if matches!(args.command, Command::ApplyResidual { .. }) {
+        apply_residual(args.command);
+        return;
+    }
+    // Reject an invalid hex watermark as a usage error, before loading the models.
+    if let Command::Encode {
+        watermark: Some(hex),
+        mode: Some(Mode::Bytes),
+        ..
+    } = &args.command
+    {
+        if let Err(err) = parse_hex(hex) {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid value '{hex}' for '--watermark': {err}"),
+                )
+                .exit();
+        }
+    }
+    // Only load the model the command needs.
+    let mut builder = Trustmark::builder(args.command.get_variant(), args.command.get_version())
+        .encoder(matches!(args.command, Command::Encode { .. }))
+        .decoder(matches!(
+            args.command,
+            Command::Decode { .. }
+                | Command::Encode {
+                    target_jpeg: Some(_),
+                    ..
+                }
+                | Command::Encode { verify: true, .. }
+        ))
+        .remover(matches!(args.command, Command::Remove { .. }));
+    if let Some(threads) = args.threads {
+        builder = builder.intra_threads(threads);
+    }
+    let tm = match &args.models {
+        Some(models) => builder.build(models),
+        None => builder.build_embedded(),
+    }
     .unwrap();
     match args.command {
         Command::Encode {
             input,
             output,
             watermark,
+            mode,
             version,
             quality,
+            strength,
+            target_psnr,
+            target_jpeg,
+            verify,
+            verify_jpeg,
+            aspect_ratio_limit,
+            tiled,
+            region,
+            mask,
+            dither,
+            redistribute,
+            layer,
             ..
         } => {
             let input = image::open(input).unwrap();
-            let watermark = watermark.unwrap_or_else(|| {
-                gen_watermark(version.unwrap_or(Version::Bch5).data_bits().into())
-            });
-            let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
-
-            let format = ImageFormat::from_path(&output).unwrap();
-            match format {
-                // JPEG encoding can make visual artifacts worse, so we encode with a higher
-                // quality than the default (or the quality requested by the user).
-                ImageFormat::Jpeg => {
-                    let quality = quality.unwrap_or(90);
-                    let mut writer = OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(&output)
+            let bits = version.unwrap_or(Version::Bch5).data_bits().into();
+            let watermark = match mode.unwrap_or_default() {
+                Mode::Binary => Payload::Binary(watermark.unwrap_or_else(|| gen_watermark(bits))),
+                Mode::Text => Payload::Text(watermark.unwrap_or_else(|| gen_text_watermark(bits))),
+                Mode::Bytes => Payload::Bytes(match watermark {
+                    Some(hex) => parse_hex(&hex).expect("hex was validated before loading models"),
+                    None => Standard
+                        .sample_iter(rand::thread_rng())
+                        .take(bits / 8)
+                        .collect(),
+                }),
+            };
+            let mut options = EncodeOptions {
+                strength: Some(strength.unwrap_or(0.95)),
+                tiling: tiling(tiled),
+                region: match (region, mask) {
+                    (Some(rect), _) => Some(Region::Rect(rect)),
+                    (None, Some(mask)) => Some(Region::Mask(image::open(mask).unwrap().to_luma8())),
+                    (None, None) => None,
+                },
+                dither: if dither {
+                    Dither::FloydSteinberg
+                } else {
+                    Dither::None
+                },
+                gamut: gamut(redistribute),
+                strength_search: match (target_psnr, target_jpeg) {
+                    (Some(psnr), _) => Some(StrengthSearch::new(StrengthTarget::Psnr(psnr))),
+                    (None, Some(quality)) => {
+                        Some(StrengthSearch::new(StrengthTarget::Jpeg(quality)))
+                    }
+                    (None, None) => None,
+                },
+                ..Default::default()
+            };
+            if let Some(limit) = aspect_ratio_limit {
+                options.aspect_ratio_limit = limit;
+            }
+            let report = match layer {
+                // The residual is computed once, then both applied and saved as the layer.
+                Some(path) => {
+                    let residual = tm
+                        .compute_residual(watermark, input.clone(), &options)
                         .unwrap();
-                    let encoder = JpegEncoder::new_with_quality(&mut writer, quality);
-                    encoded.to_rgb8().write_with_encoder(encoder).unwrap();
+                    let layer = residual
+                        .layer(input.dimensions(), layer_format(&path))
+                        .unwrap();
+                    layer.save(&path).unwrap();
+                    residual.apply_with_report(input)
                 }
-                _ => {
-                    encoded.to_rgba8().save(&output).unwrap();
+                None if verify => {
+                    let verify = Verify {
+                        jpeg_quality: verify_jpeg,
+                        ..Default::default()
+                    };
+                    tm.encode_verified(watermark, input, &options, &verify)
                 }
+                None => tm.encode_with_report(watermark, input, &options),
             }
+            .unwrap();
+            println!("Strength: {:.3}", report.strength);
+            println!("PSNR: {:.2}dB", report.psnr);
+            println!("SSIM: {:.4}", report.ssim);
+            println!("Clipped: {:.1}% of the watermark", report.clipped * 100.);
+            save(&report.image, &output, quality);
         }
-        Command::Decode { input, .. } => {
+        Command::Remove {
+            input,
+            output,
+            quality,
+            aspect_ratio_limit,
+            ..
+        } => {
             let input = image::open(input).unwrap();
-            match tm.decode(input) {
-                Ok(decoded) => println!("Found watermark: {decoded}"),
-                Err(trustmark::Error::CorruptWatermark) => {
-                    println!("Corrupt or missing watermark")
-                }
-                err => panic!("{err:?}"),
+            let mut options = RemoveOptions::default();
+            if let Some(limit) = aspect_ratio_limit {
+                options.aspect_ratio_limit = limit;
             }
+            let removed = tm.remove_with_options(input, &options).unwrap();
+            save(&removed, &output, quality);
+        }
+        Command::Decode {
+            input,
+            mode,
+            chase_bits,
+            aspect_ratio_limit,
+            tiled,
+            search,
+            region,
+            ..
+        } => {
+            let input = image::open(input).unwrap();
+            let options = decode_options(chase_bits, aspect_ratio_limit, tiled, search, region);
+            print_decoded(
+                tm.decode_with_options(input, &options),
+                mode.unwrap_or_default(),
+                false,
+            );
         }
+        Command::Eval { .. } => unreachable!("eval loads its own models"),
+        Command::ApplyResidual { .. } => unreachable!("apply-residual doesn't load models"),
     }
 }
@@ -0,0 +1,78 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use std::path::PathBuf;
+
+use ndarray::ArrayD;
+
+use crate::Error;
+
+#[cfg(any(test, feature = "mock"))]
+pub(crate) mod mock;
+#[cfg(feature = "ort")]
+pub(crate) mod ort;
+#[cfg(feature = "tract")]
+pub(crate) mod tract;
+
+#[cfg(not(any(feature = "ort", feature = "tract")))]
+compile_error!("at least one of the `ort` and `tract` features must be enabled");
+
+/// Runs the Trustmark models.
+///
+/// Images are passed as `[N, 3, size, size]` arrays of RGB values normalized to `[-1, 1]`, and
+/// watermarks as `[N, 100]` arrays. Every method returns [`Error::EncoderNotLoaded`] (or the
+/// equivalent) if its model wasn't loaded.
+///
+/// The `ort` and `tract` features each provide a backend, chosen with
+/// [`TrustmarkBuilder::backend`]. Other backends can be used with
+/// [`TrustmarkBuilder::build_with_backend`].
+///
+/// [`TrustmarkBuilder::backend`]: crate::TrustmarkBuilder::backend
+/// [`TrustmarkBuilder::build_with_backend`]: crate::TrustmarkBuilder::build_with_backend
+pub trait InferenceBackend: Send + Sync {
+    /// Run the encoder on a batch of images and the watermark bits (0s and 1s) to encode into
+    /// them, returning the encoded images.
+    fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error>;
+
+    /// Run the decoder on a batch of images, returning the logits of each watermark bit.
+    fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error>;
+
+    /// Run the remover on a batch of images, returning the cleaned images.
+    ///
+    /// As in the Python implementation, the images are `256`x`256` with channels between -1 and
+    /// 1, and the cleaned images must have the same shape.
+    fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error>;
+}
+
+/// A built-in [`InferenceBackend`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    /// The ONNX runtime, through the `ort` crate. Requires the `ort` feature, which is enabled by
+    /// default.
+    #[cfg(feature = "ort")]
+    Ort,
+    /// The pure-Rust `tract` crate, which doesn't need the native ONNX runtime. Requires the
+    /// `tract` feature.
+    #[cfg(feature = "tract")]
+    Tract,
+}
+
+/// Where to load a model from.
+pub(crate) enum ModelSource<'a> {
+    File(PathBuf),
+    Memory(&'a [u8]),
+}
+
+impl Default for Backend {
+    /// `ort` if its feature is enabled, otherwise `tract`.
+    fn default() -> Self {
+        #[cfg(feature = "ort")]
+        return Backend::Ort;
+        #[cfg(not(feature = "ort"))]
+        return Backend::Tract;
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use ndarray::{ArrayD, Ix4};
+
+use super::InferenceBackend;
+use crate::Error;
+
+/// The number of cells along each side of the grid, one cell per watermark bit.
+const GRID: usize = 10;
+
+/// The number of periods of the pattern which carries each bit along each side of the image.
+const PERIODS: usize = 32;
+
+/// How strongly the encoder adds the pattern. The residual is clamped to this anyway.
+const AMPLITUDE: f32 = 0.2;
+
+/// A deterministic stand-in for the Trustmark models, for testing without model files.
+///
+/// The image is split into a 10x10 grid of cells, one for each of the 100 watermark bits. The
+/// encoder adds a checkerboard pattern to each cell, inverted for '0' bits, and the decoder
+/// correlates each cell with the pattern. The remover subtracts the pattern it finds. Since the
+/// grid and pattern scale with the image, the watermark survives the resizing done around the
+/// models, but not much else.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MockBackend;
+
+/// The sign of the checkerboard pattern at `(y, x)` in an image of the given size.
+///
+/// The pattern scales with the image, so that the P variant's smaller decoder input still lines
+/// up with the encoder's.
+fn pattern(y: usize, x: usize, (height, width): (usize, usize)) -> f32 {
+    if (y * 2 * PERIODS / height + x * 2 * PERIODS / width) % 2 == 0 {
+        1.
+    } else {
+        -1.
+    }
+}
+
+/// The cell (and so the watermark bit) that `(y, x)` belongs to in an image of the given size.
+fn cell(y: usize, x: usize, (height, width): (usize, usize)) -> usize {
+    (y * GRID / height) * GRID + x * GRID / width
+}
+
+/// Correlate each cell of each image with the pattern, giving a `[N, 100]` array.
+fn correlate(images: &ndarray::Array4<f32>) -> ArrayD<f32> {
+    let (n, channels, height, width) = images.dim();
+    let mut sums = ndarray::Array2::<f32>::zeros((n, GRID * GRID));
+    let mut counts = vec![0.; GRID * GRID];
+    for ((i, c, y, x), value) in images.indexed_iter() {
+        let cell = cell(y, x, (height, width));
+        sums[[i, cell]] += value * pattern(y, x, (height, width));
+        if i == 0 && c == 0 {
+            counts[cell] += channels as f32;
+        }
+    }
+    for mut row in sums.rows_mut() {
+        row.iter_mut()
+            .zip(&counts)
+            .for_each(|(sum, count)| *sum /= count);
+    }
+    sums.into_dyn()
+}
+
+fn into_images(images: ArrayD<f32>) -> Result<ndarray::Array4<f32>, Error> {
+    images
+        .into_dimensionality::<Ix4>()
+        .map_err(|err| Error::Backend(err.into()))
+}
+
+impl InferenceBackend for MockBackend {
+    fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let mut images = into_images(images)?;
+        let (_, _, height, width) = images.dim();
+        for ((i, _, y, x), value) in images.indexed_iter_mut() {
+            let sign = 2. * bits[[i, cell(y, x, (height, width))]] - 1.;
+            *value += AMPLITUDE * sign * pattern(y, x, (height, width));
+        }
+        Ok(images.into_dyn())
+    }
+
+    fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        Ok(correlate(&into_images(images)?))
+    }
+
+    fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let mut images = into_images(images)?;
+        let (_, _, height, width) = images.dim();
+        let found = correlate(&images);
+        for ((i, _, y, x), value) in images.indexed_iter_mut() {
+            *value -= found[[i, cell(y, x, (height, width))]] * pattern(y, x, (height, width));
+        }
+        Ok(images.into_dyn())
+    }
+}
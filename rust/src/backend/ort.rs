@@ -0,0 +1,46 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use ndarray::ArrayD;
+use ort::Session;
+
+use super::InferenceBackend;
+use crate::Error;
+
+/// An [`InferenceBackend`] running ONNX runtime sessions.
+pub(crate) struct OrtBackend {
+    pub(crate) encoder: Option<Session>,
+    pub(crate) decoder: Option<Session>,
+    pub(crate) remover: Option<Session>,
+}
+
+impl InferenceBackend for OrtBackend {
+    fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let encoder = self.encoder.as_ref().ok_or(Error::EncoderNotLoaded)?;
+        let outputs = encoder.run(ort::inputs![
+            "onnx::Concat_0" => ort::Value::from_array(images)?,
+            "onnx::Gemm_1" => ort::Value::from_array(bits)?,
+        ]?)?;
+        Ok(outputs["image"].try_extract_tensor::<f32>()?.to_owned())
+    }
+
+    fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let decoder = self.decoder.as_ref().ok_or(Error::DecoderNotLoaded)?;
+        let outputs = decoder.run(ort::inputs![
+            "image" => ort::Value::from_array(images)?,
+        ]?)?;
+        Ok(outputs["output"].try_extract_tensor::<f32>()?.to_owned())
+    }
+
+    fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let remover = self.remover.as_ref().ok_or(Error::RemoverNotLoaded)?;
+        // The remover's input and output names depend on how it was exported, so they are used by
+        // position.
+        let outputs = remover.run(ort::inputs![ort::Value::from_array(images)?]?)?;
+        Ok(outputs[0].try_extract_tensor::<f32>()?.to_owned())
+    }
+}
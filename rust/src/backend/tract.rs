@@ -0,0 +1,76 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use ndarray::ArrayD;
+use tract_onnx::prelude::*;
+
+use super::{InferenceBackend, ModelSource};
+use crate::Error;
+
+/// A model loaded by `tract`.
+pub(crate) type Model = TypedRunnableModel<TypedModel>;
+
+/// An [`InferenceBackend`] running models with `tract`.
+pub(crate) struct TractBackend {
+    pub(crate) encoder: Option<Model>,
+    pub(crate) decoder: Option<Model>,
+    pub(crate) remover: Option<Model>,
+}
+
+/// Load and optimize an ONNX model.
+pub(crate) fn load(source: ModelSource<'_>) -> Result<Model, Error> {
+    let onnx = tract_onnx::onnx();
+    let model = match source {
+        ModelSource::File(path) => onnx.model_for_path(path),
+        ModelSource::Memory(mut bytes) => onnx.model_for_read(&mut bytes),
+    }
+    .map_err(backend_error)?;
+    model
+        .into_optimized()
+        .and_then(|model| model.into_runnable())
+        .map_err(backend_error)
+}
+
+/// Run `model` on `inputs`, which are passed in the order the model declares them, and return
+/// its first output.
+fn run(model: &Model, inputs: Vec<ArrayD<f32>>) -> Result<ArrayD<f32>, Error> {
+    let inputs = inputs
+        .into_iter()
+        .map(|input| {
+            let shape = input.shape().to_vec();
+            let data: Vec<f32> = input.iter().copied().collect();
+            Ok(Tensor::from_shape(&shape, &data)?.into())
+        })
+        .collect::<TractResult<TVec<TValue>>>()
+        .map_err(backend_error)?;
+    let outputs = model.run(inputs).map_err(backend_error)?;
+
+    let output = &outputs[0];
+    let data = output.as_slice::<f32>().map_err(backend_error)?.to_vec();
+    ArrayD::from_shape_vec(output.shape(), data).map_err(|err| Error::Backend(err.into()))
+}
+
+fn backend_error(err: TractError) -> Error {
+    Error::Backend(err.into())
+}
+
+impl InferenceBackend for TractBackend {
+    fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let encoder = self.encoder.as_ref().ok_or(Error::EncoderNotLoaded)?;
+        run(encoder, vec![images, bits])
+    }
+
+    fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let decoder = self.decoder.as_ref().ok_or(Error::DecoderNotLoaded)?;
+        run(decoder, vec![images])
+    }
+
+    fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+        let remover = self.remover.as_ref().ok_or(Error::RemoverNotLoaded)?;
+        run(remover, vec![images])
+    }
+}
@@ -7,23 +7,32 @@
 
 use std::{fmt::Display, str::FromStr};
 
-use ndarray::{Array1, ArrayD, Axis};
-use ort::{TensorValueType, Value};
+use ndarray::{Array1, Array2, ArrayD, Axis};
+
+use crate::MAX_CHASE_BITS;
 
 const VERSION_BITS: u16 = 4;
 
 mod bch;
+mod payload;
+
+pub use payload::{Mode, Payload};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(super) struct Bits(String);
 
+/// Details of the error correction performed while constructing a `Bits`.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Correction {
+    /// The number of bit flips which were corrected.
+    pub(super) bitflips: u8,
+    /// Whether the version identifier was corrupt, so that the other versions had to be tried.
+    pub(super) version_fallback: bool,
+}
+
 /// Error type for the `bits` module.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    /// Something went wrong while doing inference.
-    #[error("onnx error: {0}")]
-    Ort(#[from] ort::Error),
-
     /// A character was encounted that was not a '0' or a '1'. Strings that specify bitstrings
     /// should only use these two characters.
     #[error("allowed chars are '0' and '1'")]
@@ -47,6 +56,15 @@ pub enum Error {
     #[error("invalid version")]
     InvalidVersion,
 
+    /// String does not represent a known payload mode.
+    #[error("invalid mode")]
+    InvalidMode,
+
+    /// A text payload contained a character which is not ASCII. Text payloads are packed into 7
+    /// bits per character, so only ASCII characters can be represented.
+    #[error("text payloads may only contain ASCII characters")]
+    InvalidText,
+
     /// Watermark is missing or corrupted.
     ///
     /// Either the image did not have a valid watermark, or too many transmission errors/image
@@ -74,8 +92,8 @@ impl Bits {
             });
         }
 
-        // pad the input
-        input.push_str(&"0".repeat(data_bits - input.len() + (8 - data_bits % 8)));
+        // pad the input to a whole number of bytes, as in the Python implementation
+        input.push_str(&"0".repeat(data_bits - input.len() + (8 - data_bits % 8) % 8));
 
         // pack the input into bytes
         let data: Vec<u8> = input
@@ -131,7 +149,9 @@ impl Bits {
     /// corrects them if there are fewer bitflips than are supported by the version. As a last
     /// resort, this function checks for bitflips in the version identifier by trying all possible
     /// versions.
-    fn new(s: String) -> Result<Self, Error> {
+    ///
+    /// The details of the error correction which was needed are returned alongside the `Bits`.
+    fn new(s: String) -> Result<(Self, Correction), Error> {
         if s.chars().any(|c| c != '0' && c != '1') {
             return Err(Error::InvalidChar);
         }
@@ -142,8 +162,14 @@ impl Bits {
 
         let version: Version = Version::from_bitstring(&s[96..]).unwrap_or_default();
 
-        if let Ok(bits) = Bits::new_with_version(&s, version) {
-            Ok(bits)
+        if let Ok((bits, bitflips)) = Bits::new_with_version(&s, version) {
+            Ok((
+                bits,
+                Correction {
+                    bitflips,
+                    version_fallback: false,
+                },
+            ))
         } else {
             let mut versions = vec![
                 Version::Bch3,
@@ -156,23 +182,33 @@ impl Bits {
             for version in versions {
                 res = Some(Bits::new_with_version(&s, version));
                 if res.as_ref().unwrap().is_ok() {
-                    return res.unwrap();
+                    break;
                 }
             }
-            res.unwrap()
+            res.unwrap().map(|(bits, bitflips)| {
+                (
+                    bits,
+                    Correction {
+                        bitflips,
+                        version_fallback: true,
+                    },
+                )
+            })
         }
     }
 
-    fn new_with_version(s: &str, version: Version) -> Result<Self, Error> {
+    /// Construct a `Bits` assuming the bitstring uses `version`, returning the number of bit
+    /// flips which were corrected.
+    fn new_with_version(s: &str, version: Version) -> Result<(Self, u8), Error> {
         let data_bits: usize = version.data_bits().into();
         let ecc_bits: usize = version.ecc_bits().into();
 
         let mut data = s[..data_bits].to_string();
         let mut ecc = s[data_bits..data_bits + ecc_bits].to_string();
 
-        // pad
-        data.push_str(&"0".repeat(data_bits - data.len() + (8 - data_bits % 8)));
-        ecc.push_str(&"0".repeat(ecc_bits - ecc.len() + (8 - ecc_bits % 8)));
+        // pad to a whole number of bytes
+        data.push_str(&"0".repeat(data_bits - data.len() + (8 - data_bits % 8) % 8));
+        ecc.push_str(&"0".repeat(ecc_bits - ecc.len() + (8 - ecc_bits % 8) % 8));
 
         // pack into bytes
         let mut data: Vec<u8> = data
@@ -208,11 +244,102 @@ impl Bits {
         data.truncate(data_bits);
         ecc.truncate(ecc_bits);
 
-        Ok(Bits(format!("{data}{ecc}{}", version.bitstring())))
+        Ok((
+            Bits(format!("{data}{ecc}{}", version.bitstring())),
+            bitflips,
+        ))
+    }
+
+    /// Construct a `Bits` from the raw output of the decoder, as in [`Bits::new`].
+    ///
+    /// Logits below 0 are read as '0' bits, and all others as '1' bits.
+    pub(super) fn from_logits(array: ArrayD<f32>) -> Result<(Self, Correction), Error> {
+        Bits::new(threshold(&array)?)
+    }
+
+    /// Construct a `Bits` from the raw output of the decoder using soft-decision (Chase)
+    /// decoding.
+    ///
+    /// If the thresholded bitstring can't be corrected, every combination of the `chase_bits`
+    /// (at most [`MAX_CHASE_BITS`]) least confident data and error correction bits is flipped in
+    /// turn, and each resulting bitstring is corrected as in [`Bits::new`]. Of the candidates
+    /// which can be corrected, the one closest to the decoder output is chosen, where each bit
+    /// which differs from the thresholded bitstring costs the magnitude of its logit.
+    ///
+    /// `Correction::bitflips` counts every bit which differs from the thresholded bitstring,
+    /// including those flipped by the search.
+    pub(super) fn from_logits_chase(
+        array: ArrayD<f32>,
+        chase_bits: u8,
+    ) -> Result<(Self, Correction), Error> {
+        let hard = threshold(&array)?;
+        if let Ok(res) = Bits::new(hard.clone()) {
+            return Ok(res);
+        }
+
+        let logits: Vec<f32> = array.iter().copied().collect();
+
+        // The version bits are left alone, since `Bits::new` already tries every version.
+        let mut positions: Vec<usize> = (0..(100 - VERSION_BITS as usize)).collect();
+        positions.sort_by(|a, b| logits[*a].abs().total_cmp(&logits[*b].abs()));
+        positions.truncate(chase_bits.min(MAX_CHASE_BITS).into());
+
+        let mut best: Option<(f32, Bits, Correction)> = None;
+        for pattern in 1_u64..(1 << positions.len()) {
+            let mut candidate = hard.clone().into_bytes();
+            for (i, position) in positions.iter().enumerate() {
+                if pattern & (1 << i) != 0 {
+                    candidate[*position] ^= b'0' ^ b'1';
+                }
+            }
+
+            let Ok((bits, correction)) = Bits::new(String::from_utf8(candidate).unwrap()) else {
+                continue;
+            };
+
+            let mut cost = 0.;
+            let mut bitflips = 0;
+            for ((corrected, received), logit) in bits.0.bytes().zip(hard.bytes()).zip(&logits) {
+                if corrected != received {
+                    cost += logit.abs();
+                    bitflips += 1;
+                }
+            }
+
+            if best
+                .as_ref()
+                .map_or(true, |(best_cost, ..)| cost < *best_cost)
+            {
+                best = Some((
+                    cost,
+                    bits,
+                    Correction {
+                        bitflips,
+                        version_fallback: correction.version_fallback,
+                    },
+                ));
+            }
+        }
+
+        best.map(|(_, bits, correction)| (bits, correction))
+            .ok_or(Error::CorruptWatermark)
     }
 }
 
-impl From<Bits> for ort::Value<TensorValueType<f32>> {
+/// Threshold the raw output of the decoder into a bitstring.
+///
+/// Logits below 0 are read as '0' bits, and all others as '1' bits.
+fn threshold(array: &ArrayD<f32>) -> Result<String, Error> {
+    if array.shape() != [1, 100] {
+        return Err(Error::InvalidDim);
+    }
+    Ok(array
+        .iter()
+        .map(|bit| if *bit < 0. { '0' } else { '1' })
+        .collect())
+}
+
+impl From<Bits> for Array2<f32> {
     fn from(Bits(s): Bits) -> Self {
         let floats: Vec<f32> = s
             .chars()
@@ -223,8 +350,7 @@ impl From<Bits> for ort::Value<TensorValueType<f32>> {
             })
             .collect();
 
-        let array = Array1::from(floats);
-        Value::from_array(array.insert_axis(Axis(0))).unwrap()
+        Array1::from(floats).insert_axis(Axis(0))
     }
 }
 
@@ -232,17 +358,7 @@ impl TryFrom<ArrayD<f32>> for Bits {
     type Error = Error;
 
     fn try_from(array: ArrayD<f32>) -> Result<Self, Self::Error> {
-        if array.shape() != [1, 100] {
-            return Err(Error::InvalidDim);
-        }
-        let array = array.remove_axis(Axis(0));
-        let mut s = String::new();
-        for bit in array.iter() {
-            let c = if *bit < 0. { '0' } else { '1' };
-            s.push(c);
-        }
-
-        Bits::new(s)
+        Bits::from_logits(array).map(|(bits, _)| bits)
     }
 }
 
@@ -360,7 +476,7 @@ mod tests {
     #[test]
     fn new() {
         let input = "1011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001".to_owned();
-        let bits = Bits::new(input).unwrap();
+        let (bits, _) = Bits::new(input).unwrap();
         assert_eq!(
             bits.get_data(),
             "1011011110011000111111000000011111011111011100000110110110111"
@@ -377,17 +493,64 @@ mod tests {
     #[test]
     fn single_bitflip() {
         let input = "0011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001".to_owned();
-        let bits = Bits::new(input).unwrap();
+        let (bits, _) = Bits::new(input).unwrap();
         assert_eq!(
             bits.get_data(),
             "1011011110011000111111000000011111011111011100000110110110111"
         );
     }
 
+    #[test]
+    fn single_bitflip_correction() {
+        let input = "0011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001".to_owned();
+        let (_, correction) = Bits::new(input).unwrap();
+        assert_eq!(correction.bitflips, 1);
+        assert!(!correction.version_fallback);
+    }
+
+    #[test]
+    fn bch_super_bitflip_correction() {
+        let data = "1011011110011000111111000000011111011111".to_owned();
+        let Bits(mut s) =
+            Bits::apply_error_correction_and_schema(data.clone(), Version::BchSuper).unwrap();
+        let flipped = if &s[9..10] == "0" { "1" } else { "0" };
+        s.replace_range(9..10, flipped);
+        let (bits, correction) = Bits::new(s).unwrap();
+        assert_eq!(correction.bitflips, 1);
+        assert_eq!(bits.get_data(), data);
+    }
+
+    /// Packets from the Python `DataLayer`, so that the two implementations stay compatible.
+    #[test]
+    fn python_vectors() {
+        let full = "101101111001100011111100000001111101111101110000011011011011101011001010110";
+        let vectors = [
+            (Version::BchSuper, "0110001000000000000000000000000000000000011101111010010010001111100000010011111010001001000001110000", "1011011110011000111111000000011111011111110000100100010011001000011100110111110011101010011100100000"),
+            (Version::Bch5, "0110001000000000000000000000000000000000000000000000000000000010000001111100101011100000000010000001", "1011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001"),
+            (Version::Bch4, "0110001000000000000000000000000000000000000000000000000000000000000010000010110101111101100101110010", "1011011110011000111111000000011111011111011100000110110110111010110001111001111111001011011011000010"),
+            (Version::Bch3, "0110001000000000000000000000000000000000000000000000000000000000000000000000011000110001101000110011", "1011011110011000111111000000011111011111011100000110110110111010110010101101110011101000000101010011"),
+        ];
+        for (version, short, long) in vectors {
+            let data = full[..version.data_bits() as usize].to_owned();
+            for (input, expected) in [("0110001".to_owned(), short), (data, long)] {
+                let Bits(s) = Bits::apply_error_correction_and_schema(input, version).unwrap();
+                assert_eq!(s, expected, "{version:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_version_correction() {
+        let input = "0011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000011".to_owned();
+        let (bits, correction) = Bits::new(input).unwrap();
+        assert_eq!(bits.get_version(), Version::Bch5);
+        assert!(correction.version_fallback);
+    }
+
     #[test]
     fn single_bitflip_and_corrupted_version() {
         let input = "0011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000011".to_owned();
-        let bits = Bits::new(input).unwrap();
+        let (bits, _) = Bits::new(input).unwrap();
         assert_eq!(
             bits.get_data(),
             "1011011110011000111111000000011111011111011100000110110110111"
@@ -414,6 +577,46 @@ mod tests {
         assert!(matches!(err, Error::CorruptWatermark));
     }
 
+    /// Build decoder logits for `input`, where the bits at `flipped` are wrong but unconfident.
+    fn logits(input: &str, flipped: &[usize]) -> ArrayD<f32> {
+        let logits: Vec<f32> = input
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let logit = if c == '1' { 1. } else { -1. };
+                if flipped.contains(&i) {
+                    -0.1 * logit
+                } else {
+                    logit
+                }
+            })
+            .collect();
+        ArrayD::from_shape_vec(ndarray::IxDyn(&[1, 100]), logits).unwrap()
+    }
+
+    #[test]
+    fn chase_beyond_hard_limit() {
+        let input = "1011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001";
+        let flipped = [0, 3, 10, 20, 30, 40, 50];
+        let err = Bits::from_logits(logits(input, &flipped)).unwrap_err();
+        assert!(matches!(err, Error::CorruptWatermark));
+
+        let (bits, correction) = Bits::from_logits_chase(logits(input, &flipped), 8).unwrap();
+        assert_eq!(correction.bitflips, 7);
+        assert_eq!(
+            bits.get_data(),
+            "1011011110011000111111000000011111011111011100000110110110111"
+        );
+    }
+
+    #[test]
+    fn chase_without_errors() {
+        let input = "1011011110011000111111000000011111011111011100000110110110111000110010101101111010011011000010000001";
+        let (bits, correction) = Bits::from_logits_chase(logits(input, &[]), 8).unwrap();
+        assert_eq!(correction.bitflips, 0);
+        assert_eq!(bits.get_version(), Version::Bch5);
+    }
+
     #[test]
     fn invalid_dim() {
         let ar = ArrayD::<f32>::zeros(ndarray::IxDyn(&[3]));
@@ -344,10 +344,11 @@ pub(super) fn bch_encode(ecc_state: &mut EccState, data: &[u8]) -> Vec<u8> {
         let tmp = data[posn];
         let cyclic_tab = ecc_state.cyclic_tab.as_ref().unwrap();
         posn += 1;
-        let pidx = (l + 1) * (((ecc[0] >> 24) ^ (tmp as u32)) & 0xff);
+        let mut pidx = (l + 1) * (((ecc[0] >> 24) ^ (tmp as u32)) & 0xff);
         for i in 0..l {
             ecc[i as usize] = ((ecc[i as usize] << 8) | (ecc[(i + 1) as usize] >> 24))
                 ^ cyclic_tab[pidx as usize];
+            pidx += 1;
         }
         ecc[l as usize] = (ecc[l as usize] << 8) ^ cyclic_tab[pidx as usize];
         leftdata -= 1;
@@ -0,0 +1,187 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use std::{fmt::Display, str::FromStr};
+
+use super::Error;
+
+/// The data carried by a watermark.
+///
+/// Every watermark stores a fixed number of data bits (see [`Version::data_bits`]). A `Payload`
+/// describes how those bits are interpreted, and is packed the same way as the `binary` and `text`
+/// modes of the Python implementation so that watermarks can be exchanged between the two.
+///
+/// A `String` converts into a [`Payload::Binary`].
+///
+/// [`Version::data_bits`]: crate::Version::data_bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// A bitstring containing only '0' and '1' characters.
+    Binary(String),
+    /// ASCII text, packed into 7 bits per character.
+    Text(String),
+    /// Raw bytes, packed into 8 bits per byte.
+    Bytes(Vec<u8>),
+}
+
+/// How to interpret the data bits of a decoded watermark.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Mode {
+    #[default]
+    Binary,
+    Text,
+    Bytes,
+}
+
+impl Payload {
+    /// Get the mode of this payload.
+    pub fn mode(&self) -> Mode {
+        match self {
+            Payload::Binary(_) => Mode::Binary,
+            Payload::Text(_) => Mode::Text,
+            Payload::Bytes(_) => Mode::Bytes,
+        }
+    }
+
+    /// Pack this payload into a bitstring.
+    ///
+    /// The bitstring is not padded, so it may be shorter than the data bits of a version.
+    pub(crate) fn to_bitstring(&self) -> Result<String, Error> {
+        match self {
+            Payload::Binary(s) => Ok(s.clone()),
+            Payload::Text(s) => {
+                if !s.is_ascii() {
+                    return Err(Error::InvalidText);
+                }
+                Ok(s.bytes().map(|byte| format!("{byte:07b}")).collect())
+            }
+            Payload::Bytes(bytes) => Ok(bytes.iter().map(|byte| format!("{byte:08b}")).collect()),
+        }
+    }
+
+    /// Unpack the data bits of a watermark into a payload of the requested `mode`.
+    ///
+    /// Text is unpacked 7 bits at a time, after padding the data bits to a whole number of bytes,
+    /// and has trailing NUL characters and surrounding whitespace removed. Bytes are unpacked 8
+    /// bits at a time, ignoring any trailing bits which don't make up a whole byte.
+    pub(crate) fn from_bitstring(data: &str, mode: Mode) -> Self {
+        match mode {
+            Mode::Binary => Payload::Binary(data.to_owned()),
+            Mode::Text => {
+                let mut data = data.to_owned();
+                data.push_str(&"0".repeat((8 - data.len() % 8) % 8));
+
+                let text: String = data
+                    .as_bytes()
+                    .chunks(7)
+                    .map(|chunk| {
+                        u8::from_str_radix(std::str::from_utf8(chunk).unwrap(), 2).unwrap() as char
+                    })
+                    .collect();
+                Payload::Text(text.trim_end_matches('\0').trim().to_owned())
+            }
+            Mode::Bytes => Payload::Bytes(
+                data.as_bytes()
+                    .chunks_exact(8)
+                    .map(|chunk| {
+                        u8::from_str_radix(std::str::from_utf8(chunk).unwrap(), 2).unwrap()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<String> for Payload {
+    fn from(value: String) -> Self {
+        Payload::Binary(value)
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(value: Vec<u8>) -> Self {
+        Payload::Bytes(value)
+    }
+}
+
+impl Display for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Payload::Binary(s) | Payload::Text(s) => f.write_str(s),
+            Payload::Bytes(bytes) => bytes.iter().try_for_each(|byte| write!(f, "{byte:02x}")),
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Mode::Binary => "binary",
+            Mode::Text => "text",
+            Mode::Bytes => "bytes",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "binary" => Mode::Binary,
+            "text" => Mode::Text,
+            "bytes" => Mode::Bytes,
+            _ => return Err(Error::InvalidMode),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_packing() {
+        let payload = Payload::Text("Hi".to_owned());
+        assert_eq!(payload.to_bitstring().unwrap(), "10010001101001");
+    }
+
+    #[test]
+    fn non_ascii_text() {
+        let err = Payload::Text("héllo".to_owned())
+            .to_bitstring()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidText));
+    }
+
+    #[test]
+    fn text_roundtrip() {
+        // 61 data bits, as in `Version::Bch5`.
+        let mut data = Payload::Text("hello".to_owned()).to_bitstring().unwrap();
+        data.push_str(&"0".repeat(61 - data.len()));
+        assert_eq!(
+            Payload::from_bitstring(&data, Mode::Text),
+            Payload::Text("hello".to_owned())
+        );
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let mut data = Payload::Bytes(vec![0xde, 0xad]).to_bitstring().unwrap();
+        data.push_str(&"0".repeat(40 - data.len()));
+        assert_eq!(
+            Payload::from_bitstring(&data, Mode::Bytes),
+            Payload::Bytes(vec![0xde, 0xad, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn display_bytes() {
+        assert_eq!(Payload::Bytes(vec![0x0f, 0xa0]).to_string(), "0fa0");
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use std::path::Path;
+
+#[cfg(feature = "ort")]
+use ort::{GraphOptimizationLevel, Session, SessionBuilder};
+
+#[cfg(feature = "ort")]
+use crate::backend::ort::OrtBackend;
+#[cfg(feature = "tract")]
+use crate::backend::tract::{self, TractBackend};
+use crate::{
+    backend::{Backend, InferenceBackend, ModelSource},
+    embedded,
+    options::DEFAULT_STRENGTH,
+    Error, Trustmark, Variant, Version,
+};
+
+/// A builder for a [`Trustmark`], created with [`Trustmark::builder`].
+///
+/// The builder controls which backend runs the models, how the ONNX sessions are configured, and
+/// which of the models are loaded.
+/// By default the encoder and decoder are loaded with 8 intra-op threads and all graph
+/// optimizations enabled, which is what [`Trustmark::new`] does.
+///
+/// ```rust,no_run
+/// use trustmark::{Trustmark, Variant, Version};
+///
+/// # fn main() {
+/// // A decode-only instance for a small container.
+/// let tm = Trustmark::builder(Variant::Q, Version::Bch5)
+///     .intra_threads(2)
+///     .encoder(false)
+///     .build("./models")
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TrustmarkBuilder {
+    variant: Variant,
+    version: Version,
+    intra_threads: usize,
+    inter_threads: Option<usize>,
+    optimization_level: OptimizationLevel,
+    memory_arena: bool,
+    memory_pattern: bool,
+    strength: f32,
+    encoder: bool,
+    decoder: bool,
+    remover: bool,
+    backend: Backend,
+}
+
+/// The contents of the ONNX model files, for [`TrustmarkBuilder::build_from_memory`].
+///
+/// Models which are shared elsewhere, such as in an `Arc<[u8]>`, can be borrowed with `&*models`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ModelBytes<'a> {
+    /// The contents of `encoder_{V}.onnx`.
+    pub encoder: Option<&'a [u8]>,
+    /// The contents of `decoder_{V}.onnx`.
+    pub decoder: Option<&'a [u8]>,
+    /// The contents of `remover_{V}.onnx`.
+    pub remover: Option<&'a [u8]>,
+}
+
+/// How much the ONNX runtime optimizes the model graphs when loading them.
+///
+/// Higher levels take longer to load, but run faster.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum OptimizationLevel {
+    /// No graph optimizations.
+    Disable,
+    /// Semantics-preserving rewrites such as constant folding.
+    Level1,
+    /// Level 1, plus node fusions.
+    Level2,
+    /// All optimizations, including layout optimizations.
+    #[default]
+    Level3,
+}
+
+impl Trustmark {
+    /// Create a [`TrustmarkBuilder`] for the given variant and version.
+    pub fn builder(variant: Variant, version: Version) -> TrustmarkBuilder {
+        TrustmarkBuilder {
+            variant,
+            version,
+            intra_threads: 8,
+            inter_threads: None,
+            optimization_level: OptimizationLevel::default(),
+            memory_arena: true,
+            memory_pattern: true,
+            strength: DEFAULT_STRENGTH,
+            encoder: true,
+            decoder: true,
+            remover: false,
+            backend: Backend::default(),
+        }
+    }
+}
+
+impl TrustmarkBuilder {
+    /// Set the number of threads used to parallelize the execution within each operator.
+    /// Defaults to 8.
+    pub fn intra_threads(mut self, threads: usize) -> Self {
+        self.intra_threads = threads;
+        self
+    }
+
+    /// Set the number of threads used to run independent operators in parallel.
+    ///
+    /// Setting this enables parallel execution of the graph. By default, operators are run
+    /// sequentially.
+    pub fn inter_threads(mut self, threads: usize) -> Self {
+        self.inter_threads = Some(threads);
+        self
+    }
+
+    /// Set the graph optimization level. Defaults to [`OptimizationLevel::Level3`].
+    pub fn optimization_level(mut self, level: OptimizationLevel) -> Self {
+        self.optimization_level = level;
+        self
+    }
+
+    /// Enable or disable the CPU memory arena. Enabled by default.
+    ///
+    /// The arena keeps memory allocated between runs to make later runs faster. Disabling it
+    /// lowers the memory held by idle sessions.
+    pub fn memory_arena(mut self, enable: bool) -> Self {
+        self.memory_arena = enable;
+        self
+    }
+
+    /// Enable or disable memory pattern optimization. Enabled by default.
+    ///
+    /// Memory patterns pre-allocate memory for inputs of the same shape as earlier runs.
+    pub fn memory_pattern(mut self, enable: bool) -> Self {
+        self.memory_pattern = enable;
+        self
+    }
+
+    /// Set the strength used when [`EncodeOptions::strength`] isn't given. Defaults to 0.95.
+    ///
+    /// [`EncodeOptions::strength`]: crate::EncodeOptions::strength
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    /// Whether to load the encoder model. Loaded by default.
+    pub fn encoder(mut self, load: bool) -> Self {
+        self.encoder = load;
+        self
+    }
+
+    /// Whether to load the decoder model. Loaded by default.
+    pub fn decoder(mut self, load: bool) -> Self {
+        self.decoder = load;
+        self
+    }
+
+    /// Whether to load the remover model. Not loaded by default.
+    pub fn remover(mut self, load: bool) -> Self {
+        self.remover = load;
+        self
+    }
+
+    /// Set the backend used to run the models. Defaults to [`Backend::Ort`] if the `ort` feature
+    /// is enabled, and [`Backend::Tract`] otherwise.
+    ///
+    /// The thread, optimization, and memory options only apply to the `ort` backend.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Load the requested models from the `models` directory.
+    pub fn build<P: AsRef<Path>>(self, models: P) -> Result<Trustmark, Error> {
+        let models = models.as_ref();
+        let source =
+            |load: bool, filename: String| load.then(|| ModelSource::File(models.join(filename)));
+
+        let encoder = source(self.encoder, self.variant.encoder_filename());
+        let decoder = source(self.decoder, self.variant.decoder_filename());
+        let remover = source(self.remover, self.variant.remover_filename());
+        self.load(encoder, decoder, remover)
+    }
+
+    /// Load the requested models from the bytes of their ONNX files.
+    ///
+    /// Returns [`Error::MissingModelBytes`] if a requested model isn't present in `models`.
+    pub fn build_from_memory<'a>(self, models: ModelBytes<'a>) -> Result<Trustmark, Error> {
+        let source = |load: bool, bytes: Option<&'a [u8]>, model| -> Result<_, Error> {
+            if !load {
+                return Ok(None);
+            }
+            let bytes = bytes.ok_or(Error::MissingModelBytes(model))?;
+            Ok(Some(ModelSource::Memory(bytes)))
+        };
+
+        let encoder = source(self.encoder, models.encoder, "encoder")?;
+        let decoder = source(self.decoder, models.decoder, "decoder")?;
+        let remover = source(self.remover, models.remover, "remover")?;
+        self.load(encoder, decoder, remover)
+    }
+
+    /// Load the requested models from those embedded in the crate with one of the `embed-*`
+    /// features.
+    ///
+    /// Returns [`Error::VariantNotEmbedded`] if the models for this builder's variant weren't
+    /// embedded.
+    pub fn build_embedded(self) -> Result<Trustmark, Error> {
+        let models = embedded::models(self.variant).ok_or(Error::VariantNotEmbedded)?;
+        self.build_from_memory(models)
+    }
+
+    /// Use a custom [`InferenceBackend`] to run the models, instead of loading them.
+    ///
+    /// Which models are loaded, and how, is up to the backend, so only the variant, version and
+    /// strength of this builder are used.
+    pub fn build_with_backend(self, backend: impl InferenceBackend + 'static) -> Trustmark {
+        Trustmark {
+            backend: Box::new(backend),
+            strength: self.strength,
+            version: self.version,
+            variant: self.variant,
+        }
+    }
+
+    /// Load the given models with the requested backend.
+    fn load(
+        self,
+        encoder: Option<ModelSource<'_>>,
+        decoder: Option<ModelSource<'_>>,
+        remover: Option<ModelSource<'_>>,
+    ) -> Result<Trustmark, Error> {
+        match self.backend {
+            #[cfg(feature = "ort")]
+            Backend::Ort => {
+                let load = |source: Option<ModelSource<'_>>| -> Result<_, Error> {
+                    let Some(source) = source else {
+                        return Ok(None);
+                    };
+                    let builder = self.session_builder()?;
+                    Ok(Some(match source {
+                        ModelSource::File(path) => builder.commit_from_file(path)?,
+                        ModelSource::Memory(bytes) => builder.commit_from_memory(bytes)?,
+                    }))
+                };
+                let backend = OrtBackend {
+                    encoder: load(encoder)?,
+                    decoder: load(decoder)?,
+                    remover: load(remover)?,
+                };
+                Ok(self.build_with_backend(backend))
+            }
+            #[cfg(feature = "tract")]
+            Backend::Tract => {
+                let backend = TractBackend {
+                    encoder: encoder.map(tract::load).transpose()?,
+                    decoder: decoder.map(tract::load).transpose()?,
+                    remover: remover.map(tract::load).transpose()?,
+                };
+                Ok(self.build_with_backend(backend))
+            }
+        }
+    }
+
+    /// Create an ONNX session builder with the requested options.
+    #[cfg(feature = "ort")]
+    fn session_builder(&self) -> Result<SessionBuilder, Error> {
+        let mut builder = Session::builder()?
+            .with_optimization_level(self.optimization_level.into())?
+            .with_intra_threads(self.intra_threads)?
+            .with_memory_pattern(self.memory_pattern)?;
+        if let Some(threads) = self.inter_threads {
+            builder = builder
+                .with_parallel_execution(true)?
+                .with_inter_threads(threads)?;
+        }
+        if !self.memory_arena {
+            builder = disable_cpu_mem_arena(builder)?;
+        }
+        Ok(builder)
+    }
+}
+
+/// Disable the CPU memory arena of a session, which `ort` has no safe wrapper for.
+#[cfg(feature = "ort")]
+fn disable_cpu_mem_arena(builder: SessionBuilder) -> Result<SessionBuilder, Error> {
+    // SAFETY: the API pointer lives for the rest of the program, and `builder.ptr()` points to the
+    // session options owned by `builder`, which outlives this call.
+    let failed = unsafe {
+        let api = ort::api();
+        let status = (api.as_ref().DisableCpuMemArena.unwrap())(builder.ptr());
+        let failed = !status.is_null();
+        if failed {
+            (api.as_ref().ReleaseStatus.unwrap())(status);
+        }
+        failed
+    };
+
+    if failed {
+        Err(Error::InvalidSessionOptions)
+    } else {
+        Ok(builder)
+    }
+}
+
+#[cfg(feature = "ort")]
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(value: OptimizationLevel) -> Self {
+        match value {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
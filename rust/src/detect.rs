@@ -0,0 +1,177 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use std::path::Path;
+
+use image::DynamicImage;
+
+use crate::{embedded, DecodeOptions, DecodeReport, Error, Trustmark, Variant, Version};
+
+/// A decoder which detects which model variant a watermark was encoded with.
+///
+/// Watermarks can only be decoded by the decoder of the variant they were encoded with. This
+/// runs the decoder of every loaded variant, and reports the watermark which needed the fewest
+/// bit flips to be corrected. The variant it was found with is in [`DecodeReport::variant`].
+///
+/// Like [`Trustmark`], it can be shared between threads.
+///
+/// ```rust,no_run
+/// use trustmark::MultiVariantDecoder;
+///
+/// # fn main() {
+/// let decoder = MultiVariantDecoder::new("./models").unwrap();
+/// let input = image::open("../images/ghost.png").unwrap();
+/// let report = decoder.decode(input).unwrap();
+/// println!("{} (variant {})", report.data, report.variant);
+/// # }
+/// ```
+pub struct MultiVariantDecoder {
+    decoders: Vec<Trustmark>,
+}
+
+impl MultiVariantDecoder {
+    /// Load the decoders of every variant from the `models` directory.
+    pub fn new<P: AsRef<Path>>(models: P) -> Result<Self, Error> {
+        let models = models.as_ref();
+        let decoders = Variant::ALL
+            .iter()
+            .map(|&variant| {
+                Trustmark::builder(variant, Version::default())
+                    .encoder(false)
+                    .build(models)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { decoders })
+    }
+
+    /// Load the decoders of every variant embedded in the crate with the `embed-*` features.
+    ///
+    /// Returns [`Error::VariantNotEmbedded`] if no variants were embedded.
+    pub fn embedded() -> Result<Self, Error> {
+        let decoders: Vec<_> = embedded::variants()
+            .map(|variant| {
+                Trustmark::builder(variant, Version::default())
+                    .encoder(false)
+                    .build_embedded()
+            })
+            .collect::<Result<_, _>>()?;
+        if decoders.is_empty() {
+            return Err(Error::VariantNotEmbedded);
+        }
+        Ok(Self { decoders })
+    }
+
+    /// Detect between the given Trustmarks, each of which must have a decoder loaded.
+    ///
+    /// This allows the sessions to be configured with a [`TrustmarkBuilder`], or only some of
+    /// the variants to be considered.
+    ///
+    /// [`TrustmarkBuilder`]: crate::TrustmarkBuilder
+    pub fn from_decoders(decoders: Vec<Trustmark>) -> Self {
+        Self { decoders }
+    }
+
+    /// Get the variants this decoder detects between.
+    pub fn variants(&self) -> impl Iterator<Item = Variant> + '_ {
+        self.decoders.iter().map(|decoder| decoder.variant)
+    }
+
+    /// Decode a watermark from an image with every variant, using the default options.
+    pub fn decode(&self, img: DynamicImage) -> Result<DecodeReport, Error> {
+        self.decode_with_options(img, &DecodeOptions::default())
+    }
+
+    /// Decode a watermark from an image with every variant.
+    ///
+    /// Returns the watermark with the fewest corrected bits, preferring earlier variants on ties,
+    /// or [`Error::CorruptWatermark`] if no variant found a valid watermark.
+    pub fn decode_with_options(
+        &self,
+        img: DynamicImage,
+        options: &DecodeOptions,
+    ) -> Result<DecodeReport, Error> {
+        best(
+            self.decoders
+                .iter()
+                .map(|decoder| decoder.decode_with_options(img.clone(), options)),
+        )
+    }
+}
+
+/// Pick the report with the fewest corrected bits.
+///
+/// Corrupt watermarks are skipped, but any other error is returned.
+pub(crate) fn best(
+    reports: impl Iterator<Item = Result<DecodeReport, Error>>,
+) -> Result<DecodeReport, Error> {
+    let mut best: Option<DecodeReport> = None;
+    for report in reports {
+        match report {
+            Ok(report) => {
+                if best
+                    .as_ref()
+                    .map_or(true, |best| report.corrected_bits < best.corrected_bits)
+                {
+                    best = Some(report);
+                }
+            }
+            Err(Error::CorruptWatermark) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    best.ok_or(Error::CorruptWatermark)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(variant: Variant, corrected_bits: u8) -> Result<DecodeReport, Error> {
+        Ok(DecodeReport {
+            data: String::new(),
+            version: Version::Bch5,
+            variant,
+            corrected_bits,
+            version_fallback: false,
+            logits: vec![],
+            transform: None,
+        })
+    }
+
+    #[test]
+    fn fewest_corrected_bits() {
+        let reports = vec![
+            report(Variant::B, 3),
+            Err(Error::CorruptWatermark),
+            report(Variant::P, 0),
+            report(Variant::Q, 0),
+        ];
+        assert_eq!(best(reports.into_iter()).unwrap().variant, Variant::P);
+    }
+
+    #[test]
+    fn all_corrupt() {
+        let reports = vec![Err(Error::CorruptWatermark), Err(Error::CorruptWatermark)];
+        assert!(matches!(
+            best(reports.into_iter()),
+            Err(Error::CorruptWatermark)
+        ));
+    }
+
+    #[test]
+    fn detect_ghost() {
+        let tm = Trustmark::new("./models", Variant::C, Version::Bch5).unwrap();
+        let input = image::open("../images/ghost.png").unwrap();
+        let watermark = "1011011110011000111111000000011111011111011100000110110110111".to_owned();
+        let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+
+        let decoder = MultiVariantDecoder::new("./models").unwrap();
+        let report = decoder.decode(encoded).unwrap();
+        assert_eq!(report.variant, Variant::C);
+        assert_eq!(report.data, watermark);
+    }
+}
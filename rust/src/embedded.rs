@@ -0,0 +1,82 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+//! Models embedded in the crate by the `embed-*` features.
+//!
+//! Each feature embeds the encoder and decoder of one variant from the `models/` directory, so
+//! the models must have been fetched with `cargo xtask fetch-models` before building.
+
+use crate::{ModelBytes, Variant};
+
+/// The `(variant, encoder, decoder)` models which were embedded.
+const EMBEDDED: &[(Variant, &[u8], &[u8])] = &[
+    #[cfg(feature = "embed-b")]
+    (
+        Variant::B,
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/encoder_B.onnx"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/decoder_B.onnx"
+        )),
+    ),
+    #[cfg(feature = "embed-c")]
+    (
+        Variant::C,
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/encoder_C.onnx"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/decoder_C.onnx"
+        )),
+    ),
+    #[cfg(feature = "embed-p")]
+    (
+        Variant::P,
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/encoder_P.onnx"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/decoder_P.onnx"
+        )),
+    ),
+    #[cfg(feature = "embed-q")]
+    (
+        Variant::Q,
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/encoder_Q.onnx"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/models/decoder_Q.onnx"
+        )),
+    ),
+];
+
+/// Get the embedded models for `variant`, if it was embedded.
+pub(super) fn models(variant: Variant) -> Option<ModelBytes<'static>> {
+    EMBEDDED
+        .iter()
+        .find(|(embedded, ..)| *embedded == variant)
+        .map(|(_, encoder, decoder)| ModelBytes {
+            encoder: Some(encoder),
+            decoder: Some(decoder),
+            remover: None,
+        })
+}
+
+/// Get the variants which were embedded.
+pub(super) fn variants() -> impl Iterator<Item = Variant> {
+    EMBEDDED.iter().map(|(variant, ..)| *variant)
+}
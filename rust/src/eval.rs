@@ -0,0 +1,446 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+//! Measures of how well watermarks survive common edits.
+//!
+//! [`evaluate`] watermarks a set of images with random payloads, applies each [`Attack`] to every
+//! watermarked image, and decodes the results. The [`Evaluation`] of each attack can be written
+//! out with [`to_csv`] or [`to_json`].
+//!
+//! ```rust,no_run
+//! use trustmark::{eval, EncodeOptions, Trustmark, Variant, Version};
+//!
+//! let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+//! let images = vec![image::open("../images/ghost.png").unwrap()];
+//! let evaluations =
+//!     eval::evaluate(&tm, images, &eval::Attack::suite(), &EncodeOptions::default()).unwrap();
+//! println!("{}", eval::to_csv(&evaluations));
+//! ```
+
+use std::{fmt::Display, str::FromStr};
+
+use image::{imageops, DynamicImage, GenericImageView as _, Rgba, Rgba32FImage};
+use ndarray::Array2;
+
+use crate::{
+    bits::Bits, image_processing, Decoding, EncodeOptions, Error, Trustmark, Variant, Version,
+};
+
+/// The seed of the random payloads and noise, so that evaluations can be repeated.
+const SEED: u64 = 0x7275_7374_6d61_726b;
+
+/// An edit which a watermark should survive.
+///
+/// Attacks are written as their name and parameter separated by a colon, such as `jpeg:75`, both
+/// by [`Display`] and by [`FromStr`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Attack {
+    /// Leave the image as it is, to measure the watermark without any attack.
+    None,
+    /// Compress the image as a JPEG of this quality, from 1 to 100.
+    Jpeg(u8),
+    /// Resize the image by this factor, which must be positive.
+    Resize(f32),
+    /// Crop the center of the image, keeping this fraction of its width and height, above 0 and
+    /// at most 1.
+    Crop(f32),
+    /// Blur the image with a Gaussian of this standard deviation, in pixels, which must not be
+    /// negative.
+    Blur(f32),
+    /// Add Gaussian noise of this standard deviation to each channel, where 1 is the range
+    /// from black to white. The deviation must not be negative.
+    Noise(f32),
+    /// Rotate the image about its center by this many degrees clockwise, keeping its size and
+    /// filling the corners with black.
+    Rotate(f32),
+}
+
+impl Attack {
+    /// A suite of attacks ranging from mild to severe, which social media and content
+    /// distribution platforms commonly apply.
+    pub fn suite() -> Vec<Attack> {
+        vec![
+            Attack::None,
+            Attack::Jpeg(90),
+            Attack::Jpeg(75),
+            Attack::Jpeg(50),
+            Attack::Resize(0.5),
+            Attack::Resize(0.25),
+            Attack::Crop(0.9),
+            Attack::Crop(0.75),
+            Attack::Blur(1.0),
+            Attack::Blur(2.0),
+            Attack::Noise(0.02),
+            Attack::Noise(0.05),
+            Attack::Rotate(2.0),
+            Attack::Rotate(5.0),
+        ]
+    }
+
+    /// Apply the attack to `img`, drawing any noise from `rng`.
+    fn apply(&self, img: &DynamicImage, rng: &mut Rng) -> Result<DynamicImage, Error> {
+        let (width, height) = img.dimensions();
+        let scaled = |size: u32, factor: f32| ((size as f32 * factor).round() as u32).max(1);
+        Ok(match *self {
+            Attack::None => img.clone(),
+            Attack::Jpeg(quality) => image_processing::jpeg_roundtrip(img, quality)?,
+            Attack::Resize(factor) => img.resize_exact(
+                scaled(width, factor),
+                scaled(height, factor),
+                imageops::FilterType::Triangle,
+            ),
+            Attack::Crop(fraction) => {
+                let (crop_width, crop_height) = (scaled(width, fraction), scaled(height, fraction));
+                img.crop_imm(
+                    (width.saturating_sub(crop_width)) / 2,
+                    (height.saturating_sub(crop_height)) / 2,
+                    crop_width,
+                    crop_height,
+                )
+            }
+            Attack::Blur(sigma) => img.blur(sigma),
+            Attack::Noise(sigma) => {
+                let mut noisy = img.to_rgba32f();
+                for pixel in noisy.pixels_mut() {
+                    for channel in &mut pixel.0[..3] {
+                        *channel = (*channel + sigma * rng.gaussian()).clamp(0., 1.);
+                    }
+                }
+                noisy.into()
+            }
+            Attack::Rotate(degrees) => rotate(img, degrees),
+        })
+    }
+}
+
+impl Display for Attack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Attack::None => write!(f, "none"),
+            Attack::Jpeg(quality) => write!(f, "jpeg:{quality}"),
+            Attack::Resize(factor) => write!(f, "resize:{factor}"),
+            Attack::Crop(fraction) => write!(f, "crop:{fraction}"),
+            Attack::Blur(sigma) => write!(f, "blur:{sigma}"),
+            Attack::Noise(sigma) => write!(f, "noise:{sigma}"),
+            Attack::Rotate(degrees) => write!(f, "rotate:{degrees}"),
+        }
+    }
+}
+
+impl FromStr for Attack {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = s.split_once(':').unwrap_or((s, ""));
+        // Each parameter is finite and within the range its attack can apply.
+        let float = |valid: fn(f32) -> bool| {
+            parameter
+                .parse::<f32>()
+                .ok()
+                .filter(|&value| value.is_finite() && valid(value))
+                .ok_or(Error::InvalidAttack)
+        };
+        Ok(match name {
+            "none" => Attack::None,
+            "jpeg" => Attack::Jpeg(match parameter.parse() {
+                Ok(quality @ 1..=100) => quality,
+                _ => return Err(Error::InvalidAttack),
+            }),
+            "resize" => Attack::Resize(float(|factor| factor > 0.)?),
+            "crop" => Attack::Crop(float(|fraction| fraction > 0. && fraction <= 1.)?),
+            "blur" => Attack::Blur(float(|sigma| sigma >= 0.)?),
+            "noise" => Attack::Noise(float(|sigma| sigma >= 0.)?),
+            "rotate" => Attack::Rotate(float(|_| true)?),
+            _ => return Err(Error::InvalidAttack),
+        })
+    }
+}
+
+/// How well the watermarks of a set of images survived an [`Attack`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    /// The model variant the images were watermarked and decoded with.
+    pub variant: Variant,
+    /// The error correction schema of the watermarks.
+    pub version: Version,
+    /// The attack applied to the watermarked images.
+    pub attack: Attack,
+    /// The number of images evaluated.
+    pub images: usize,
+    /// The mean fraction of the 100 watermark bits which the decoder read correctly, before
+    /// error correction.
+    pub bit_accuracy: f32,
+    /// The fraction of images whose watermark was recovered by the error correction.
+    pub success_rate: f32,
+    /// How many recovered watermarks needed each number of bit flips corrected, indexed by the
+    /// number of bit flips.
+    pub corrected_bits: Vec<usize>,
+}
+
+/// Watermark each of `images` with a random payload, then report how well the watermarks
+/// survive each of `attacks`.
+///
+/// The images are watermarked with `options`, and decoded with the same aspect ratio limit, but
+/// without searching or tiling. Images which can't be watermarked fail the evaluation. The payloads
+/// and noise are drawn from a fixed seed, so an evaluation of the same images gives the same
+/// results.
+pub fn evaluate(
+    tm: &Trustmark,
+    images: impl IntoIterator<Item = DynamicImage>,
+    attacks: &[Attack],
+    options: &EncodeOptions,
+) -> Result<Vec<Evaluation>, Error> {
+    let mut rng = Rng(SEED);
+    let mut evaluations: Vec<_> = attacks
+        .iter()
+        .map(|&attack| Evaluation {
+            variant: tm.variant,
+            version: tm.version,
+            attack,
+            images: 0,
+            bit_accuracy: 0.,
+            success_rate: 0.,
+            corrected_bits: Vec::new(),
+        })
+        .collect();
+
+    for img in images {
+        let data: String = (0..tm.version.data_bits())
+            .map(|_| if rng.next() & 1 == 1 { '1' } else { '0' })
+            .collect();
+        let expected: Array2<f32> =
+            Bits::apply_error_correction_and_schema(data.clone(), tm.version)?.into();
+        let watermarked = tm.encode_with_options(data.clone(), img, options)?;
+
+        let attacked = attacks
+            .iter()
+            .map(|attack| attack.apply(&watermarked, &mut rng))
+            .collect::<Result<Vec<_>, _>>()?;
+        let inputs = attacked
+            .iter()
+            .map(|img| tm.model_inputs(tm.decode_size(), img, options.aspect_ratio_limit, &[]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let logits = tm
+            .backend
+            .decode(image_processing::stack(inputs.iter().flatten())?)?;
+
+        for (evaluation, logits) in evaluations
+            .iter_mut()
+            .zip(image_processing::unstack(logits.view()))
+        {
+            let correct = logits
+                .iter()
+                .zip(&expected)
+                .filter(|&(logit, bit)| (*logit >= 0.) == (*bit > 0.5))
+                .count();
+            evaluation.images += 1;
+            evaluation.bit_accuracy += correct as f32 / expected.len() as f32;
+            if let Ok(report) = tm.decode_report(logits, Decoding::Hard) {
+                if report.data == data {
+                    evaluation.success_rate += 1.;
+                    let corrected = report.corrected_bits as usize;
+                    if evaluation.corrected_bits.len() <= corrected {
+                        evaluation.corrected_bits.resize(corrected + 1, 0);
+                    }
+                    evaluation.corrected_bits[corrected] += 1;
+                }
+            }
+        }
+    }
+
+    for evaluation in &mut evaluations {
+        let images = evaluation.images.max(1) as f32;
+        evaluation.bit_accuracy /= images;
+        evaluation.success_rate /= images;
+    }
+    Ok(evaluations)
+}
+
+/// Write `evaluations` as CSV, with a header row and the corrected bit flips as a list separated
+/// by semicolons.
+pub fn to_csv(evaluations: &[Evaluation]) -> String {
+    let mut csv =
+        String::from("variant,version,attack,images,bit_accuracy,success_rate,corrected_bits\n");
+    for evaluation in evaluations {
+        let corrected_bits: Vec<_> = evaluation
+            .corrected_bits
+            .iter()
+            .map(usize::to_string)
+            .collect();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            evaluation.variant,
+            evaluation.version,
+            evaluation.attack,
+            evaluation.images,
+            evaluation.bit_accuracy,
+            evaluation.success_rate,
+            corrected_bits.join(";"),
+        ));
+    }
+    csv
+}
+
+/// Write `evaluations` as a JSON array of objects, with the same fields as [`Evaluation`].
+pub fn to_json(evaluations: &[Evaluation]) -> String {
+    let objects: Vec<_> = evaluations
+        .iter()
+        .map(|evaluation| {
+            let corrected_bits: Vec<_> = evaluation
+                .corrected_bits
+                .iter()
+                .map(usize::to_string)
+                .collect();
+            format!(
+                r#"{{"variant":"{}","version":"{}","attack":"{}","images":{},"bit_accuracy":{},"success_rate":{},"corrected_bits":[{}]}}"#,
+                evaluation.variant,
+                evaluation.version,
+                evaluation.attack,
+                evaluation.images,
+                evaluation.bit_accuracy,
+                evaluation.success_rate,
+                corrected_bits.join(","),
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+/// Rotate `img` about its center, keeping its size.
+fn rotate(img: &DynamicImage, degrees: f32) -> DynamicImage {
+    let source = img.to_rgba32f();
+    let (width, height) = source.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (center_x, center_y) = ((width as f32 - 1.) / 2., (height as f32 - 1.) / 2.);
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        // Find where this pixel was before the rotation.
+        let (dx, dy) = (x as f32 - center_x, y as f32 - center_y);
+        let source_x = center_x + cos * dx + sin * dy;
+        let source_y = center_y - sin * dx + cos * dy;
+        imageops::interpolate_bilinear(&source, source_x, source_y)
+            .unwrap_or(Rgba([0., 0., 0., 1.]))
+    })
+    .into()
+}
+
+/// A small deterministic random number generator, so that evaluations don't need a dependency
+/// on `rand`.
+struct Rng(u64);
+
+impl Rng {
+    /// The next number of the SplitMix64 sequence.
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number uniformly distributed in (0, 1].
+    fn uniform(&mut self) -> f32 {
+        ((self.next() >> 40) as f32 + 1.) / (1u64 << 24) as f32
+    }
+
+    /// A number normally distributed with a mean of 0 and a standard deviation of 1, by the
+    /// Box-Muller transform.
+    fn gaussian(&mut self) -> f32 {
+        let (u, v) = (self.uniform(), self.uniform());
+        (-2. * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn parse_attacks() {
+        for attack in Attack::suite() {
+            assert_eq!(attack.to_string().parse::<Attack>().unwrap(), attack);
+        }
+        assert_eq!("crop:0.5".parse::<Attack>().unwrap(), Attack::Crop(0.5));
+        for invalid in [
+            "jpeg:0",
+            "jpeg:101",
+            "blur",
+            "blur:x",
+            "blur:-1",
+            "noise:-0.1",
+            "crop:0",
+            "crop:1.5",
+            "resize:0",
+            "resize:inf",
+            "rotate:NaN",
+            "sharpen:1",
+        ] {
+            assert!(invalid.parse::<Attack>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn attack_sizes() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(100, 60, Rgb([200, 100, 50])));
+        let mut rng = Rng(SEED);
+        let size = |attack: Attack, rng: &mut Rng| attack.apply(&img, rng).unwrap().dimensions();
+        assert_eq!(size(Attack::Resize(0.5), &mut rng), (50, 30));
+        assert_eq!(size(Attack::Crop(0.9), &mut rng), (90, 54));
+        for attack in [
+            Attack::Jpeg(50),
+            Attack::Blur(1.),
+            Attack::Noise(0.05),
+            Attack::Rotate(5.),
+        ] {
+            assert_eq!(size(attack, &mut rng), (100, 60));
+        }
+    }
+
+    #[test]
+    fn rotate_quarter_turn() {
+        let mut img = RgbImage::from_pixel(5, 5, Rgb([0, 0, 0]));
+        img.put_pixel(4, 2, Rgb([255, 255, 255]));
+        let rotated = rotate(&img.into(), 90.).to_rgb8();
+        // Clockwise, the middle of the right edge turns to the middle of the bottom edge.
+        assert_eq!(rotated.get_pixel(2, 4).0, [255; 3]);
+        assert_eq!(rotated.get_pixel(4, 2).0, [0; 3]);
+    }
+
+    #[test]
+    fn noise_statistics() {
+        let mut rng = Rng(SEED);
+        let samples: Vec<f32> = (0..10_000).map(|_| rng.gaussian()).collect();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / samples.len() as f32;
+        assert!(mean.abs() < 0.05, "{mean}");
+        assert!((variance - 1.).abs() < 0.05, "{variance}");
+    }
+
+    #[test]
+    fn csv_and_json() {
+        let evaluations = [Evaluation {
+            variant: Variant::Q,
+            version: Version::Bch5,
+            attack: Attack::Jpeg(75),
+            images: 4,
+            bit_accuracy: 0.975,
+            success_rate: 0.75,
+            corrected_bits: vec![1, 0, 2],
+        }];
+        assert_eq!(
+            to_csv(&evaluations),
+            "variant,version,attack,images,bit_accuracy,success_rate,corrected_bits\n\
+             Q,BCH_5,jpeg:75,4,0.975,0.75,1;0;2\n"
+        );
+        assert_eq!(
+            to_json(&evaluations),
+            r#"[{"variant":"Q","version":"BCH_5","attack":"jpeg:75","images":4,"bit_accuracy":0.975,"success_rate":0.75,"corrected_bits":[1,0,2]}]"#
+        );
+    }
+}
@@ -0,0 +1,401 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use image::{DynamicImage, Rgb, Rgb32FImage};
+
+use crate::{Error, Rect};
+
+/// The length of the short side of the proxy images the models run on in place of a frame.
+///
+/// The models run at 256x256, so larger proxies don't give them any more detail.
+const PROXY_SIZE: u32 = 256;
+
+/// The luma of each channel in BT.601, which [`PixelLayout::Nv12`] frames are stored in.
+const LUMA: [f32; 3] = [0.299, 0.587, 0.114];
+
+/// The order and format of the pixels in a [`Frame`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// Red, green and blue bytes.
+    Rgb8,
+    /// Blue, green and red bytes.
+    Bgr8,
+    /// Red, green, blue and alpha bytes. The alpha channel is left as it is.
+    Rgba8,
+    /// Blue, green, red and alpha bytes. The alpha channel is left as it is.
+    Bgra8,
+    /// A plane of luma bytes, followed by a plane of interleaved blue-difference and
+    /// red-difference chroma bytes at half the width and height, with the same stride. The colors
+    /// are BT.601 in limited range, as most video decoders and cameras produce. The width and
+    /// height must be even.
+    Nv12,
+}
+
+impl PixelLayout {
+    /// The offsets of the red, green and blue bytes in each pixel and the number of bytes in
+    /// each pixel, or `None` if the layout is planar.
+    fn packed(self) -> Option<([usize; 3], usize)> {
+        match self {
+            PixelLayout::Rgb8 => Some(([0, 1, 2], 3)),
+            PixelLayout::Bgr8 => Some(([2, 1, 0], 3)),
+            PixelLayout::Rgba8 => Some(([0, 1, 2], 4)),
+            PixelLayout::Bgra8 => Some(([2, 1, 0], 4)),
+            PixelLayout::Nv12 => None,
+        }
+    }
+
+    /// Check that a buffer of `len` bytes holds a frame of this layout with the given
+    /// dimensions and stride.
+    fn check(self, (width, height): (u32, u32), stride: usize, len: usize) -> Result<(), Error> {
+        let (width, height) = (width as usize, height as usize);
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidFrame);
+        }
+        let row = match self.packed() {
+            Some((_, bytes)) => width.checked_mul(bytes).ok_or(Error::InvalidFrame)?,
+            None => width,
+        };
+        let rows = match self {
+            PixelLayout::Nv12 if width % 2 != 0 || height % 2 != 0 => {
+                return Err(Error::InvalidFrame)
+            }
+            PixelLayout::Nv12 => height.checked_add(height / 2).ok_or(Error::InvalidFrame)?,
+            _ => height,
+        };
+        // The last row only needs its pixels, not a whole stride.
+        let needed = stride
+            .checked_mul(rows - 1)
+            .and_then(|offset| offset.checked_add(row));
+        match needed {
+            Some(needed) if stride >= row && len >= needed => Ok(()),
+            _ => Err(Error::InvalidFrame),
+        }
+    }
+}
+
+/// A borrowed buffer of pixels to decode, such as a frame from a video decoder or camera.
+///
+/// Rows of pixels are `stride` bytes apart, which may be more than the bytes of pixels in a row.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    layout: PixelLayout,
+}
+
+/// A mutably borrowed buffer of pixels to encode in place, like a [`Frame`].
+#[derive(Debug)]
+pub struct FrameMut<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    layout: PixelLayout,
+}
+
+impl<'a> Frame<'a> {
+    /// Borrow `data` as a frame of the given dimensions, stride and layout.
+    ///
+    /// Returns [`Error::InvalidFrame`] if `data` is too short or the stride too small.
+    pub fn new(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> Result<Self, Error> {
+        layout.check((width, height), stride, data.len())?;
+        Ok(Self {
+            data,
+            width,
+            height,
+            stride,
+            layout,
+        })
+    }
+
+    /// The width and height of the frame.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The color of the pixel at `(x, y)`, with each channel between 0 and 1.
+    fn rgb(&self, x: u32, y: u32) -> [f32; 3] {
+        let (x, y) = (x as usize, y as usize);
+        match self.layout.packed() {
+            Some((offsets, bytes)) => {
+                let pixel = y * self.stride + x * bytes;
+                offsets.map(|offset| self.data[pixel + offset] as f32 / 255.)
+            }
+            None => {
+                let chroma = self.chroma_offset(x, y);
+                ycbcr_to_rgb([
+                    self.data[y * self.stride + x],
+                    self.data[chroma],
+                    self.data[chroma + 1],
+                ])
+            }
+        }
+    }
+
+    /// The offset of the chroma of the pixel at `(x, y)` of a [`PixelLayout::Nv12`] frame.
+    fn chroma_offset(&self, x: usize, y: usize) -> usize {
+        (self.height as usize + y / 2) * self.stride + x / 2 * 2
+    }
+
+    /// A copy of the frame scaled down to [`PROXY_SIZE`] along its short side, averaging the
+    /// pixels which make up each pixel of the copy. Frames smaller than that are copied as they
+    /// are.
+    pub(crate) fn proxy(&self) -> DynamicImage {
+        let (width, height) = (self.width, self.height);
+        let scale = (PROXY_SIZE as f32 / width.min(height) as f32).min(1.);
+        let proxy_width = ((width as f32 * scale).round() as u32).max(1);
+        let proxy_height = ((height as f32 * scale).round() as u32).max(1);
+        Rgb32FImage::from_fn(proxy_width, proxy_height, |x, y| {
+            let mut sum = [0.; 3];
+            let mut count = 0.;
+            for y in span(y, proxy_height, height) {
+                for x in span(x, proxy_width, width) {
+                    let rgb = self.rgb(x, y);
+                    (0..3).for_each(|c| sum[c] += rgb[c]);
+                    count += 1.;
+                }
+            }
+            Rgb(sum.map(|sum| sum / count))
+        })
+        .into()
+    }
+}
+
+impl<'a> FrameMut<'a> {
+    /// Borrow `data` as a frame of the given dimensions, stride and layout.
+    ///
+    /// Returns [`Error::InvalidFrame`] if `data` is too short or the stride too small.
+    pub fn new(
+        data: &'a mut [u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> Result<Self, Error> {
+        layout.check((width, height), stride, data.len())?;
+        Ok(Self {
+            data,
+            width,
+            height,
+            stride,
+            layout,
+        })
+    }
+
+    /// The width and height of the frame.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Borrow the frame to read it.
+    pub fn as_frame(&self) -> Frame<'_> {
+        Frame {
+            data: self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            layout: self.layout,
+        }
+    }
+
+    /// Add the result of `change` to each channel of each pixel in `rect`, clipping each channel
+    /// between black and white.
+    ///
+    /// `change` is called with the position of each pixel relative to `rect`, and returns the
+    /// change to its red, green and blue channels, in units of the full range of a channel. The
+    /// chroma of a [`PixelLayout::Nv12`] frame is changed by the mean change of the pixels
+    /// sharing it.
+    pub(crate) fn apply_changes(&mut self, rect: &Rect, change: impl Fn(u32, u32) -> [f32; 3]) {
+        let Some((offsets, bytes)) = self.layout.packed() else {
+            return self.apply_changes_nv12(rect, change);
+        };
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let pixel = (rect.y + y) as usize * self.stride + (rect.x + x) as usize * bytes;
+                let change = change(x, y);
+                for (offset, change) in offsets.iter().zip(change) {
+                    let value = self.data[pixel + offset] as f32 / 255. + change;
+                    self.data[pixel + offset] = to_u8(value * 255.);
+                }
+            }
+        }
+    }
+
+    fn apply_changes_nv12(&mut self, rect: &Rect, change: impl Fn(u32, u32) -> [f32; 3]) {
+        // The chroma is only changed once both rows sharing it are done, so it is accumulated
+        // one row of chroma at a time.
+        let mut chroma_changes = vec![[0.; 2]; self.width as usize / 2];
+        let rows = rect.y..rect.y + rect.height;
+        for chroma_y in rect.y / 2..(rect.y + rect.height).div_ceil(2) {
+            chroma_changes.fill([0.; 2]);
+            for y in [chroma_y * 2, chroma_y * 2 + 1] {
+                if !rows.contains(&y) {
+                    continue;
+                }
+                for x in rect.x..rect.x + rect.width {
+                    let rgb = self.as_frame().rgb(x, y);
+                    let change = change(x - rect.x, y - rect.y);
+                    let change: [f32; 3] =
+                        [0, 1, 2].map(|c| (rgb[c] + change[c]).clamp(0., 1.) - rgb[c]);
+                    let luma = y as usize * self.stride + x as usize;
+                    let luma_change: f32 = (0..3).map(|c| LUMA[c] * change[c]).sum();
+                    self.data[luma] = to_u8(self.data[luma] as f32 + 219. * luma_change);
+                    let [blue, red] = &mut chroma_changes[x as usize / 2];
+                    *blue += (change[2] - luma_change) / 1.772;
+                    *red += (change[0] - luma_change) / 1.402;
+                }
+            }
+            for (chroma_x, [blue, red]) in chroma_changes.iter().enumerate() {
+                let chroma = self
+                    .as_frame()
+                    .chroma_offset(chroma_x * 2, chroma_y as usize * 2);
+                // Pixels outside the rectangle are unchanged, so count towards the mean as 0.
+                for (offset, change) in [(0, blue), (1, red)] {
+                    let value = self.data[chroma + offset] as f32 + 224. * change / 4.;
+                    self.data[chroma + offset] = to_u8(value);
+                }
+            }
+        }
+    }
+}
+
+/// The pixels of a frame of `length` pixels which make up pixel `i` of a scaled copy of
+/// `scaled_length` pixels.
+fn span(i: u32, scaled_length: u32, length: u32) -> std::ops::Range<u32> {
+    let (i, scaled_length, length) = (i as u64, scaled_length as u64, length as u64);
+    let start = i * length / scaled_length;
+    let end = ((i + 1) * length).div_ceil(scaled_length).max(start + 1);
+    start as u32..end as u32
+}
+
+/// Convert limited range BT.601 luma and chroma bytes to red, green and blue between 0 and 1.
+fn ycbcr_to_rgb([luma, blue, red]: [u8; 3]) -> [f32; 3] {
+    let luma = (luma as f32 - 16.) / 219.;
+    let blue = (blue as f32 - 128.) / 224.;
+    let red = (red as f32 - 128.) / 224.;
+    [
+        luma + 1.402 * red,
+        luma - 0.344136 * blue - 0.714136 * red,
+        luma + 1.772 * blue,
+    ]
+    .map(|value| value.clamp(0., 1.))
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0., 255.) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whole(frame: &FrameMut<'_>) -> Rect {
+        let (width, height) = frame.dimensions();
+        Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn packed_layouts() {
+        let rgb = [
+            10, 20, 30, 40, 50, 60, 0, 0, 70, 80, 90, 100, 110, 120, 0, 0,
+        ];
+        let bgra = [
+            30, 20, 10, 255, 60, 50, 40, 255, 90, 80, 70, 255, 120, 110, 100, 255,
+        ];
+        let rgb = Frame::new(&rgb, 2, 2, 8, PixelLayout::Rgb8).unwrap();
+        let bgra = Frame::new(&bgra, 2, 2, 8, PixelLayout::Bgra8).unwrap();
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(rgb.rgb(x, y), bgra.rgb(x, y));
+        }
+        assert_eq!(rgb.rgb(1, 1), [100. / 255., 110. / 255., 120. / 255.]);
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let data = [0; 24];
+        assert!(Frame::new(&data, 2, 2, 6, PixelLayout::Rgb8).is_ok());
+        assert!(Frame::new(&data, 2, 2, 5, PixelLayout::Rgb8).is_err());
+        assert!(Frame::new(&data, 4, 2, 12, PixelLayout::Rgba8).is_err());
+        assert!(Frame::new(&data, 4, 4, 4, PixelLayout::Nv12).is_ok());
+        assert!(Frame::new(&data, 4, 4, 5, PixelLayout::Nv12).is_err());
+        assert!(Frame::new(&data, 3, 2, 4, PixelLayout::Nv12).is_err());
+        assert!(Frame::new(&data, 0, 2, 4, PixelLayout::Rgb8).is_err());
+        assert!(Frame::new(&data, 2, 3, usize::MAX, PixelLayout::Rgb8).is_err());
+        assert!(Frame::new(&data, 2, 2, usize::MAX / 2 + 1, PixelLayout::Nv12).is_err());
+    }
+
+    #[test]
+    fn nv12_gray() {
+        // Luma 16 to 235, with neutral chroma.
+        let mut data = vec![16, 126, 235, 126];
+        data.extend([128, 128]);
+        let frame = Frame::new(&data, 2, 2, 2, PixelLayout::Nv12).unwrap();
+        assert_eq!(frame.rgb(0, 0), [0.; 3]);
+        assert_eq!(frame.rgb(0, 1), [1.; 3]);
+        let [r, g, b] = frame.rgb(1, 0);
+        assert!((r - 0.5).abs() < 0.01 && r == g && g == b);
+    }
+
+    #[test]
+    fn proxy_averages() {
+        let data: Vec<u8> = (0..1024 * 512)
+            .flat_map(|i| [(i % 2 * 200) as u8; 3])
+            .collect();
+        let frame = Frame::new(&data, 1024, 512, 3072, PixelLayout::Rgb8).unwrap();
+        let proxy = frame.proxy().into_rgb32f();
+        assert_eq!(proxy.dimensions(), (512, 256));
+        for pixel in proxy.pixels() {
+            assert!((pixel[0] - 100. / 255.).abs() < 1e-6, "{pixel:?}");
+        }
+    }
+
+    #[test]
+    fn changes_in_place() {
+        let mut data = [100, 200, 250, 7, 100, 200, 250, 7];
+        let mut frame = FrameMut::new(&mut data, 2, 1, 8, PixelLayout::Rgba8).unwrap();
+        frame.apply_changes(
+            &Rect {
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+            |_, _| [0.2, -0.2, 0.2],
+        );
+        assert_eq!(data, [100, 200, 250, 7, 151, 149, 255, 7]);
+    }
+
+    #[test]
+    fn nv12_changes() {
+        let mut data = vec![126; 16];
+        data.extend([128; 8]);
+        let mut frame = FrameMut::new(&mut data, 4, 4, 4, PixelLayout::Nv12).unwrap();
+        let rect = whole(&frame);
+        frame.apply_changes(&rect, |_, _| [0.1; 3]);
+        // A gray change brightens the luma and leaves the chroma neutral.
+        assert!(data[..16].iter().all(|&luma| luma == 148), "{data:?}");
+        assert!(data[16..].iter().all(|&chroma| chroma == 128), "{data:?}");
+
+        let mut frame = FrameMut::new(&mut data, 4, 4, 4, PixelLayout::Nv12).unwrap();
+        frame.apply_changes(&rect, |_, _| [0.1, 0., 0.]);
+        let rgb = frame.as_frame().rgb(2, 2);
+        assert!(rgb[0] > rgb[1] && rgb[0] > rgb[2], "{rgb:?}");
+    }
+}
@@ -9,14 +9,14 @@ use std::cmp;
 
 use fast_image_resize::{ResizeAlg, ResizeOptions, Resizer};
 use image::{
+    codecs::jpeg::JpegEncoder,
     imageops::{self, FilterType},
-    DynamicImage, GenericImageView as _, GrayAlphaImage, GrayImage, ImageBuffer, Pixel as _,
-    Rgb32FImage, RgbImage, Rgba32FImage, RgbaImage,
+    ColorType, DynamicImage, GenericImageView as _, GrayAlphaImage, GrayImage, ImageBuffer,
+    ImageFormat, Pixel, Rgb32FImage, RgbImage, RgbaImage,
 };
-use ndarray::{s, Array, ArrayD, Axis, ShapeError};
-use ort::TensorValueType;
+use ndarray::{s, Array, ArrayD, ArrayViewD, Axis, ShapeError};
 
-use crate::Variant;
+use crate::{Gamut, Variant};
 
 /// Re-normalize a floating point value (either scalar or array) from the range [0,1] to the range
 /// [-1, 1].
@@ -34,15 +34,20 @@ macro_rules! convert_from_neg1_1_to_0_1 {
     };
 }
 
-pub(super) struct ModelImage(pub(super) u32, pub(super) Variant, pub(super) DynamicImage);
+/// An image to be passed to (or returned from) a model.
+///
+/// The fields are the model's input size, the model variant, the aspect ratio limit above which
+/// the image is center-cropped, and the image itself.
+pub(super) struct ModelImage(
+    pub(super) u32,
+    pub(super) Variant,
+    pub(super) f32,
+    pub(super) DynamicImage,
+);
 
 /// The error type for the `image_processing` module.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    /// Something went wrong during inference.
-    #[error("onnx error: {0}")]
-    Ort(#[from] ort::Error),
-
     /// We were unable to make an `ndarray::Array` of the requested shape.
     #[error("shape error: {0}")]
     Shape(#[from] ShapeError),
@@ -58,13 +63,23 @@ pub enum Error {
     /// We were unable to resize the input image.
     #[error("resize error: {0}")]
     Resize(#[from] fast_image_resize::ResizeError),
+
+    /// We were unable to encode or decode an image file.
+    #[error("codec error: {0}")]
+    Codec(#[from] image::ImageError),
 }
 
-impl TryFrom<ModelImage> for ort::Value<TensorValueType<f32>> {
+impl TryFrom<ModelImage> for ArrayD<f32> {
     type Error = Error;
 
-    fn try_from(ModelImage(size, variant, img): ModelImage) -> Result<Self, Self::Error> {
-        let (w, h, xpos, ypos) = center_crop_size_and_offset(variant, &img);
+    fn try_from(
+        ModelImage(size, variant, aspect_ratio_limit, img): ModelImage,
+    ) -> Result<Self, Self::Error> {
+        // An empty image has nothing to resize, and no center square to crop.
+        if img.width() == 0 || img.height() == 0 {
+            return Err(Error::Image);
+        }
+        let (w, h, xpos, ypos) = center_crop_size_and_offset(variant, &img, aspect_ratio_limit);
 
         let options = ResizeOptions::new()
             .crop(xpos as f64, ypos as f64, w as f64, h as f64)
@@ -85,15 +100,15 @@ impl TryFrom<ModelImage> for ort::Value<TensorValueType<f32>> {
             .reversed_axes();
         array.swap_axes(2, 3);
         assert_eq!(array.shape(), &[1, 3, size as usize, size as usize]);
-        Ok(ort::Value::from_array(&array)?)
+        Ok(array.as_standard_layout().into_owned().into_dyn())
     }
 }
 
-impl TryFrom<(u32, Variant, ArrayD<f32>)> for ModelImage {
+impl TryFrom<(u32, Variant, f32, ArrayD<f32>)> for ModelImage {
     type Error = Error;
 
     fn try_from(
-        (size, variant, mut array): (u32, Variant, ArrayD<f32>),
+        (size, variant, aspect_ratio_limit, mut array): (u32, Variant, f32, ArrayD<f32>),
     ) -> Result<Self, Self::Error> {
         let &[1, 3, height, width] = &array.shape().to_owned()[..] else {
             return Err(Error::InvalidShape);
@@ -108,43 +123,88 @@ impl TryFrom<(u32, Variant, ArrayD<f32>)> for ModelImage {
         let image = Rgb32FImage::from_vec(width as u32, height as u32, array.to_vec())
             .ok_or(Error::Image)?;
 
-        Ok(Self(size, variant, image.into()))
+        Ok(Self(size, variant, aspect_ratio_limit, image.into()))
     }
 }
 
-/// Apply `residual` to the `input`.
+/// Stack `[1, 3, size, size]` model inputs into a single `[N, 3, size, size]` batch.
+pub(super) fn stack<'a>(
+    arrays: impl IntoIterator<Item = &'a ArrayD<f32>>,
+) -> Result<ArrayD<f32>, Error> {
+    let views: Vec<_> = arrays.into_iter().map(|array| array.view()).collect();
+    Ok(ndarray::concatenate(Axis(0), &views)?)
+}
+
+/// Split a `[N, ...]` model output into `N` arrays of shape `[1, ...]`.
+pub(super) fn unstack(array: ArrayViewD<'_, f32>) -> Vec<ArrayD<f32>> {
+    array
+        .outer_iter()
+        .map(|item| item.insert_axis(Axis(0)).to_owned())
+        .collect()
+}
+
+/// Apply `residual` to the `input`, returning the watermarked image and the fraction of the
+/// residual which was lost to clipping.
 ///
 /// This function upscales `residual` to be the size of of `input`, then adds `residual` to the
-/// `input`.
-pub(super) fn apply_residual(input: DynamicImage, residual: DynamicImage) -> DynamicImage {
+/// `input`. If there is a `mask`, it is stretched to the size of `input` too, and the residual at
+/// each pixel is scaled by it. Whatever would push a channel past black or white is handled as
+/// `gamut` says.
+pub(super) fn apply_residual(
+    input: DynamicImage,
+    residual: DynamicImage,
+    mask: Option<&GrayImage>,
+    gamut: Gamut,
+) -> (DynamicImage, f32) {
     let has_alpha = input.color().has_alpha();
     let (w, h) = input.dimensions();
 
-    let applied = {
+    let (applied, lost) = {
         let input = input.clone().into_rgba32f();
         let mut target = input.clone();
 
         let residual = residual.resize_exact(w, h, FilterType::Triangle);
         let residual = residual.into_rgba32f();
 
-        for ((target, residual), original) in target
-            .pixels_mut()
-            .zip(residual.pixels())
-            .zip(input.pixels())
-        {
-            target.apply2(residual, |x, y| {
-                let x = convert_from_0_1_to_neg1_1!(x);
-                let y = convert_from_0_1_to_neg1_1!(y);
-
-                convert_from_neg1_1_to_0_1!(f32::min(x + y, 1.0))
-            });
-            target[3] = original[3];
+        let mask = mask.map(|mask| imageops::resize(mask, w, h, FilterType::Triangle));
+        let weights: Vec<f32> = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                mask.as_ref()
+                    .map_or(1., |mask| mask.get_pixel(x, y)[0] as f32 / 255.)
+            })
+            .collect();
+
+        let mut values: Vec<[f32; 3]> = input
+            .pixels()
+            .map(|pixel| [0, 1, 2].map(|c| convert_from_0_1_to_neg1_1!(pixel[c])))
+            .collect();
+        let residuals: Vec<[f32; 3]> = residual
+            .pixels()
+            .zip(&weights)
+            .map(|(pixel, weight)| {
+                [0, 1, 2].map(|c| convert_from_0_1_to_neg1_1!(pixel[c]) * weight)
+            })
+            .collect();
+        let requested: f32 = residuals.iter().flatten().map(|y| y.abs()).sum();
+        let lost = match gamut {
+            Gamut::Clip => clip(&mut values, &residuals),
+            Gamut::Redistribute => {
+                redistribute(&mut values, &residuals, &weights, (w as usize, h as usize))
+            }
+        };
+
+        for (target, value) in target.pixels_mut().zip(values) {
+            for c in 0..3 {
+                target[c] = convert_from_neg1_1_to_0_1!(value[c]);
+            }
         }
 
-        target
+        let lost = if requested > 0. { lost / requested } else { 0. };
+        (target, lost)
     };
 
-    if has_alpha {
+    let applied = if has_alpha {
         let mut input = input.into_rgba32f();
         imageops::replace(&mut input, &applied, 0, 0);
         input.into()
@@ -153,7 +213,245 @@ pub(super) fn apply_residual(input: DynamicImage, residual: DynamicImage) -> Dyn
         let applied = DynamicImage::ImageRgba32F(applied).into_rgb32f();
         imageops::replace(&mut input, &applied, 0, 0);
         input.into()
+    };
+    (applied, lost)
+}
+
+/// The luma of each channel, as used by the `image` crate's conversions to grayscale.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The most residual which may be carried over from one pixel to the next by [`redistribute`],
+/// as much as the encoder's residual is clamped to.
+const MAX_CARRY: f32 = 0.2;
+
+/// Add `residuals` to `values`, clamping each channel between -1 and 1, and return the total
+/// residual clamped away.
+fn clip(values: &mut [[f32; 3]], residuals: &[[f32; 3]]) -> f32 {
+    let mut lost = 0.;
+    for (value, residual) in values.iter_mut().zip(residuals) {
+        for c in 0..3 {
+            let sum = value[c] + residual[c];
+            let clamped = sum.clamp(-1., 1.);
+            lost += (sum - clamped).abs().min(residual[c].abs());
+            value[c] = clamped;
+        }
     }
+    lost
+}
+
+/// Add `residuals` to `values` like [`clip`], but move whatever doesn't fit elsewhere instead of
+/// dropping it, and return the total residual which couldn't be placed anywhere.
+///
+/// The luma of the overflow of a pixel is first shifted into its channels with room left, which
+/// keeps the change in brightness the watermark is mostly made of. What still doesn't fit is
+/// diffused to the neighbouring pixels with the Floyd-Steinberg weights, up to [`MAX_CARRY`], and
+/// weighted by how much of the residual the neighbours take.
+fn redistribute(
+    values: &mut [[f32; 3]],
+    residuals: &[[f32; 3]],
+    weights: &[f32],
+    (width, height): (usize, usize),
+) -> f32 {
+    let mut carried = vec![[0f32; 3]; values.len()];
+    let mut lost = 0.;
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let value = &mut values[index];
+            let mut overflow = [0.; 3];
+            for c in 0..3 {
+                let sum = value[c] + residuals[index][c] + carried[index][c] * weights[index];
+                value[c] = sum.clamp(-1., 1.);
+                overflow[c] = sum - value[c];
+            }
+
+            let overflow_luma: f32 = (0..3).map(|c| LUMA[c] * overflow[c]).sum();
+            if overflow_luma != 0. {
+                let direction = overflow_luma.signum();
+                let room = [0, 1, 2].map(|c| {
+                    if overflow[c] == 0. {
+                        1. - direction * value[c]
+                    } else {
+                        0.
+                    }
+                });
+                let room_luma: f32 = (0..3).map(|c| LUMA[c] * room[c]).sum();
+                if room_luma > 0. {
+                    let shifted = (overflow_luma.abs() / room_luma).min(1.);
+                    for c in 0..3 {
+                        value[c] += direction * shifted * room[c];
+                    }
+                    let left = 1. - shifted * room_luma / overflow_luma.abs();
+                    overflow = overflow.map(|overflow| overflow * left);
+                }
+            }
+
+            let neighbours = [
+                (x + 1 < width).then(|| (index + 1, 7. / 16.)),
+                (x > 0 && y + 1 < height).then(|| (index + width - 1, 3. / 16.)),
+                (y + 1 < height).then(|| (index + width, 5. / 16.)),
+                (x + 1 < width && y + 1 < height).then(|| (index + width + 1, 1. / 16.)),
+            ];
+            for c in 0..3 {
+                let mut placed = 0.;
+                for (neighbour, share) in neighbours.iter().flatten() {
+                    let carry = &mut carried[*neighbour][c];
+                    let before = *carry;
+                    *carry = (*carry + share * overflow[c]).clamp(-MAX_CARRY, MAX_CARRY);
+                    placed += (*carry - before).abs() * weights[*neighbour];
+                }
+                lost += (overflow[c].abs() - placed).max(0.);
+            }
+        }
+    }
+    lost
+}
+
+/// Paste `img` over `target` at `(x, y)`, returning a floating point image like `img`.
+pub(super) fn paste(target: DynamicImage, img: &DynamicImage, (x, y): (u32, u32)) -> DynamicImage {
+    if img.color().has_alpha() {
+        let mut target = target.into_rgba32f();
+        imageops::replace(&mut target, &img.to_rgba32f(), x as i64, y as i64);
+        target.into()
+    } else {
+        let mut target = target.into_rgb32f();
+        imageops::replace(&mut target, &img.to_rgb32f(), x as i64, y as i64);
+        target.into()
+    }
+}
+
+/// Convert `img` to the given color type, such as that of the image it was made from.
+///
+/// Converting to grayscale averages the watermark across the color channels.
+pub(super) fn to_color_type(img: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => img.into_luma8().into(),
+        ColorType::La8 => img.into_luma_alpha8().into(),
+        ColorType::Rgb8 => img.into_rgb8().into(),
+        ColorType::Rgba8 => img.into_rgba8().into(),
+        ColorType::L16 => img.into_luma16().into(),
+        ColorType::La16 => img.into_luma_alpha16().into(),
+        ColorType::Rgb16 => img.into_rgb16().into(),
+        ColorType::Rgba16 => img.into_rgba16().into(),
+        ColorType::Rgb32F => img.into_rgb32f().into(),
+        ColorType::Rgba32F => img.into_rgba32f().into(),
+        // `ColorType` is non-exhaustive, and floating point keeps the most precision.
+        _ => img,
+    }
+}
+
+/// Convert `img` to the given integer color type with Floyd-Steinberg dithering.
+///
+/// Floating point color types are converted as they are, since they don't need quantizing.
+pub(super) fn dither(img: DynamicImage, color: ColorType) -> DynamicImage {
+    let max = match color {
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => u8::MAX as f32,
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => u16::MAX as f32,
+        _ => return to_color_type(img, color),
+    };
+
+    let (width, height) = img.dimensions();
+    let channels = color.channel_count() as usize;
+    let gray = !color.has_color();
+    let mut values: Vec<f32> = img
+        .into_rgba32f()
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            // The same luma weights as the `image` crate's conversions.
+            let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let all = if gray {
+                [luma, a, 0., 0.]
+            } else {
+                [r, g, b, a]
+            };
+            all.into_iter().take(channels).map(|value| value * max)
+        })
+        .collect();
+    floyd_steinberg(
+        &mut values,
+        (width as usize, height as usize),
+        channels,
+        max,
+    );
+
+    if max == u8::MAX as f32 {
+        let raw = values.into_iter().map(|value| value as u8).collect();
+        match color {
+            ColorType::L8 => DynamicImage::ImageLuma8(buffer(width, height, raw)),
+            ColorType::La8 => DynamicImage::ImageLumaA8(buffer(width, height, raw)),
+            ColorType::Rgb8 => DynamicImage::ImageRgb8(buffer(width, height, raw)),
+            _ => DynamicImage::ImageRgba8(buffer(width, height, raw)),
+        }
+    } else {
+        let raw = values.into_iter().map(|value| value as u16).collect();
+        match color {
+            ColorType::L16 => DynamicImage::ImageLuma16(buffer(width, height, raw)),
+            ColorType::La16 => DynamicImage::ImageLumaA16(buffer(width, height, raw)),
+            ColorType::Rgb16 => DynamicImage::ImageRgb16(buffer(width, height, raw)),
+            _ => DynamicImage::ImageRgba16(buffer(width, height, raw)),
+        }
+    }
+}
+
+/// An image buffer of the given size holding `raw`, which must have the right length.
+fn buffer<P: Pixel>(
+    width: u32,
+    height: u32,
+    raw: Vec<P::Subpixel>,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    ImageBuffer::from_raw(width, height, raw).expect("a subpixel for every channel of every pixel")
+}
+
+/// Round interleaved `values` between 0 and `max` to whole numbers, diffusing the rounding error
+/// of each to the values to its right and below it.
+fn floyd_steinberg(values: &mut [f32], (width, height): (usize, usize), channels: usize, max: f32) {
+    let index = |x: usize, y: usize, c: usize| (y * width + x) * channels + c;
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let old = values[index(x, y, c)];
+                let new = old.round().clamp(0., max);
+                values[index(x, y, c)] = new;
+                let error = old - new;
+                if x + 1 < width {
+                    values[index(x + 1, y, c)] += error * 7. / 16.;
+                }
+                if y + 1 < height {
+                    if x > 0 {
+                        values[index(x - 1, y + 1, c)] += error * 3. / 16.;
+                    }
+                    values[index(x, y + 1, c)] += error * 5. / 16.;
+                    if x + 1 < width {
+                        values[index(x + 1, y + 1, c)] += error / 16.;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compress `img` as a JPEG of the given `quality`, from 1 to 100, and decode it again.
+pub(super) fn jpeg_roundtrip(img: &DynamicImage, quality: u8) -> Result<DynamicImage, Error> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality).encode_image(&img.to_rgb8())?;
+    Ok(image::load_from_memory_with_format(
+        &jpeg,
+        ImageFormat::Jpeg,
+    )?)
+}
+
+/// Whether an image of the given size is center-cropped before being passed to the model.
+///
+/// Images whose aspect ratio (the longer side over the shorter side) is above `aspect_ratio_limit`
+/// are cropped, as are all images for the P variant. A limit of 1.0 always crops.
+pub(super) fn is_center_cropped(
+    variant: Variant,
+    (width, height): (u32, u32),
+    aspect_ratio_limit: f32,
+) -> bool {
+    let aspect_ratio = cmp::max(width, height) as f32 / cmp::min(width, height) as f32;
+    aspect_ratio > aspect_ratio_limit || variant == Variant::P
 }
 
 /// Return the size and offset of the "center-cropped" image.
@@ -162,10 +460,14 @@ pub(super) fn apply_residual(input: DynamicImage, residual: DynamicImage) -> Dyn
 ///
 /// For long-skinny images or short-wide images, we want to crop a square image with side length of
 /// the shorter side out of the center of the image for the model.
-fn center_crop_size_and_offset(variant: Variant, img: &DynamicImage) -> (u32, u32, u32, u32) {
+fn center_crop_size_and_offset(
+    variant: Variant,
+    img: &DynamicImage,
+    aspect_ratio_limit: f32,
+) -> (u32, u32, u32, u32) {
     let (width, height) = img.dimensions();
 
-    if height > width * 2 || width > height * 2 || variant == Variant::P {
+    if is_center_cropped(variant, (width, height), aspect_ratio_limit) {
         let m = cmp::min(height, width);
         let offset = (cmp::max(height, width) - m) / 2;
 
@@ -205,9 +507,13 @@ fn resize_img(
         }
         DynamicImage::ImageRgb16(_) => DynamicImage::ImageRgb16(ImageBuffer::new(width, height)),
         DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(ImageBuffer::new(width, height)),
-        DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb32F(Rgb32FImage::new(width, height)),
+        // `fast_image_resize` can't resize floating point images, such as those returned by
+        // `Trustmark::encode`, so these are resized at 16 bits per channel instead.
+        DynamicImage::ImageRgb32F(_) => {
+            return resize_img(&img.to_rgb16().into(), width, height, options)
+        }
         DynamicImage::ImageRgba32F(_) => {
-            DynamicImage::ImageRgba32F(Rgba32FImage::new(width, height))
+            return resize_img(&img.to_rgba16().into(), width, height, options)
         }
         // Technically unreachable, but we error for safety.
         _ => return Err(Error::Image),
@@ -217,53 +523,117 @@ fn resize_img(
     Ok(modified_img)
 }
 
+/// Return the square tiles a tiled image is watermarked in, as `(x, y, size)`.
+///
+/// As many whole tiles as fit along the longer side of the image are spread evenly across it, so
+/// that the gaps before, between and after the tiles are the same. An empty image has no tiles.
+pub(super) fn tiles((width, height): (u32, u32)) -> Vec<(u32, u32, u32)> {
+    let size = cmp::min(width, height);
+    let length = cmp::max(width, height);
+    if size == 0 {
+        return Vec::new();
+    }
+    spread_tiles(length, size, length / size)
+        .map(|offset| square_at(offset, size, width > height))
+        .collect()
+}
+
+/// Return square windows, overlapping by at least half their size, which span the longer side of
+/// an image, as `(x, y, size)`.
+///
+/// These are where tiles may be found once a tiled image has been cropped. An empty image has no
+/// windows.
+pub(super) fn windows((width, height): (u32, u32)) -> Vec<(u32, u32, u32)> {
+    let size = cmp::min(width, height);
+    let length = cmp::max(width, height);
+    if size == 0 {
+        return Vec::new();
+    }
+    let count = (length - size).div_ceil(cmp::max(size / 2, 1)) + 1;
+    (0..count)
+        .map(|i| {
+            if count == 1 {
+                0
+            } else {
+                i * (length - size) / (count - 1)
+            }
+        })
+        .map(|offset| square_at(offset, size, width > height))
+        .collect()
+}
+
+/// Return the offsets of `count` tiles of length `size`, spread evenly along `length`.
+fn spread_tiles(length: u32, size: u32, count: u32) -> impl Iterator<Item = u32> {
+    let gaps = length - count * size;
+    (0..count).map(move |i| (i + 1) * gaps / (count + 1) + i * size)
+}
+
+/// Return the square of side `size` at `offset` along the longer side of an image.
+fn square_at(offset: u32, size: u32, wide: bool) -> (u32, u32, u32) {
+    if wide {
+        (offset, 0, size)
+    } else {
+        (0, offset, size)
+    }
+}
+
 /// Applies the mean padding boundary artifact mitigation.
 ///
 /// Center cropped images have a vertical line problem along the boundary of the residual. This
 /// transformation makes this boundary less visible.
+///
+/// `residuals` are the residuals of the squares which were passed to the model: the center square
+/// if there is one residual, or the [`tiles`] otherwise.
 pub(super) fn remove_boundary_artifact(
-    mut residual: ArrayD<f32>,
+    mut residuals: Vec<ArrayD<f32>>,
     (width, height): (usize, usize),
     _variant: Variant,
 ) -> ArrayD<f32> {
-    // We're going to replace the border of the residual with the mean and also pad the non-center
-    // areas with the mean value.
+    // We're going to replace the border of each residual with the mean and also pad the
+    // non-residual areas with the mean value.
     let channel_means: Vec<f32> = (0_usize..3)
-        .map(|i| residual.slice(s![.., i, .., ..]).mean().unwrap())
+        .map(|i| {
+            residuals
+                .iter()
+                .map(|residual| residual.slice(s![.., i, .., ..]).mean().unwrap())
+                .sum::<f32>()
+                / residuals.len() as f32
+        })
         .collect();
 
     // We want one dimension of the output to be 256 and we we want the aspect ratio of the output
     // to match the input image.
+    let other = (cmp::max(width, height) as f32 / cmp::min(width, height) as f32 * 256.0) as usize;
     let mut mean_padded: ndarray::Array4<f32> = if width > height {
-        let other = ((width as f32 / height as f32) * 256.0) as usize;
         ndarray::Array4::zeros([1, 3, 256_usize, other])
     } else {
-        let other = (height / width) * 256;
         ndarray::Array4::zeros([1, 3, other, 256])
     };
 
     // This softens the transition between the residual area and the rest of the image.
     let border = 2;
     for (i, mean) in channel_means.iter().enumerate() {
-        residual.slice_mut(s![0, i, ..border, ..]).fill(*mean);
-        residual.slice_mut(s![0, i, -border.., ..]).fill(*mean);
-        residual.slice_mut(s![0, i, .., -border..]).fill(*mean);
-        residual.slice_mut(s![0, i, .., ..border]).fill(*mean);
+        for residual in &mut residuals {
+            residual.slice_mut(s![0, i, ..border, ..]).fill(*mean);
+            residual.slice_mut(s![0, i, -border.., ..]).fill(*mean);
+            residual.slice_mut(s![0, i, .., -border..]).fill(*mean);
+            residual.slice_mut(s![0, i, .., ..border]).fill(*mean);
+        }
         mean_padded.slice_mut(s![0, i, .., ..]).fill(*mean);
     }
 
-    if width > height {
-        let other = ((width as f32 / height as f32) * 256.0) as usize;
-        let leftover = (other - 256) / 2;
-        mean_padded
-            .slice_mut(s![.., .., .., leftover..(leftover + 256)])
-            .assign(&residual);
-    } else {
-        let other = ((height as f32 / width as f32) * 256.0) as usize;
-        let leftover = (other - 256) / 2;
-        mean_padded
-            .slice_mut(s![.., .., leftover..(leftover + 256), ..])
-            .assign(&residual);
+    let offsets = spread_tiles(other as u32, 256, residuals.len() as u32);
+    for (residual, offset) in residuals.iter().zip(offsets) {
+        let offset = offset as usize;
+        if width > height {
+            mean_padded
+                .slice_mut(s![.., .., .., offset..(offset + 256)])
+                .assign(residual);
+        } else {
+            mean_padded
+                .slice_mut(s![.., .., offset..(offset + 256), ..])
+                .assign(residual);
+        }
     }
 
     mean_padded.into_dyn()
@@ -273,6 +643,118 @@ pub(super) fn remove_boundary_artifact(
 mod tests {
     use super::*;
 
+    #[test]
+    fn resize_float_image() {
+        let img = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(
+            64,
+            32,
+            image::Rgb([0.25, 0.5, 1.0]),
+        ));
+        let resized = resize_img(&img, 16, 16, ResizeOptions::new()).unwrap();
+        assert_eq!(resized.dimensions(), (16, 16));
+        let pixel = resized.into_rgb32f().get_pixel(8, 8).0;
+        for (resized, original) in pixel.iter().zip([0.25, 0.5, 1.0]) {
+            assert!((resized - original).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn masked_residual() {
+        let input = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 2, image::Rgb([100; 3])));
+        let residual =
+            DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(4, 2, image::Rgb([0.75; 3])));
+        let mut mask = GrayImage::from_pixel(4, 2, image::Luma([255]));
+        mask.put_pixel(0, 0, image::Luma([0]));
+        let (applied, _) = apply_residual(input, residual, Some(&mask), Gamut::Clip);
+        let applied = applied.to_rgb8();
+        assert_eq!(applied.get_pixel(0, 0).0, [100; 3]);
+        assert_eq!(applied.get_pixel(3, 1).0, [164; 3]);
+    }
+
+    #[test]
+    fn dither_keeps_mean() {
+        let img = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(
+            64,
+            64,
+            image::Rgb([100.3 / 255., 0.5, 1.0]),
+        ));
+        let dithered = dither(img.clone(), ColorType::Rgb8);
+        assert_eq!(dithered.color(), ColorType::Rgb8);
+        let dithered = dithered.to_rgb8();
+        let mean = dithered
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32)
+            .sum::<f32>()
+            / 4096.;
+        assert!((mean - 100.3).abs() < 0.05, "{mean}");
+        assert!(dithered.pixels().all(|pixel| pixel.0[2] == 255));
+
+        let rounded = to_color_type(img, ColorType::Rgb8).to_rgb8();
+        assert!(rounded.pixels().all(|pixel| pixel.0[0] == 100));
+
+        let gray = dither(dithered.into(), ColorType::La16);
+        assert_eq!(gray.color(), ColorType::La16);
+    }
+
+    /// A residual image which adds `y` to every channel, in the -1 to 1 range of the models.
+    fn uniform_residual((width, height): (u32, u32), y: f32) -> DynamicImage {
+        DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(
+            width,
+            height,
+            image::Rgb([convert_from_neg1_1_to_0_1!(y); 3]),
+        ))
+    }
+
+    #[test]
+    fn redistribute_into_channels() {
+        let input =
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, image::Rgb([255, 200, 200])));
+        let residual = uniform_residual((1, 1), 0.1);
+
+        let (clipped, lost) = apply_residual(input.clone(), residual.clone(), None, Gamut::Clip);
+        assert!((lost - 1. / 3.).abs() < 1e-4, "{lost}");
+        assert_eq!(clipped.into_rgb32f().get_pixel(0, 0)[0], 1.);
+
+        let (redistributed, lost) =
+            apply_residual(input.clone(), residual, None, Gamut::Redistribute);
+        assert_eq!(lost, 0.);
+        // All of the brightness the residual adds is kept, in the green and blue channels.
+        let luma = |img: DynamicImage| {
+            let pixel = img.into_rgb32f().get_pixel(0, 0).0;
+            (0..3).map(|c| LUMA[c] * pixel[c]).sum::<f32>()
+        };
+        let added = luma(redistributed) - luma(input.clone());
+        let requested = LUMA.iter().sum::<f32>() * 0.05;
+        assert!((added - requested).abs() < 1e-4, "{added}");
+    }
+
+    #[test]
+    fn redistribute_into_neighbours() {
+        let mut input = RgbImage::from_pixel(2, 1, image::Rgb([128; 3]));
+        input.put_pixel(0, 0, image::Rgb([255; 3]));
+        let input = DynamicImage::ImageRgb8(input);
+        let residual = uniform_residual((2, 1), 0.1);
+
+        let (_, lost) = apply_residual(input.clone(), residual.clone(), None, Gamut::Clip);
+        assert!((lost - 0.5).abs() < 1e-4, "{lost}");
+
+        // Only the share of the overflow diffused to the right has anywhere to go.
+        let (redistributed, lost) = apply_residual(input, residual, None, Gamut::Redistribute);
+        assert!((lost - 0.5 * 9. / 16.).abs() < 1e-4, "{lost}");
+        let pixel = redistributed.into_rgb32f().get_pixel(1, 0).0;
+        let expected = 128. / 255. + 0.05 * (1. + 7. / 16.);
+        assert!((pixel[0] - expected).abs() < 1e-4, "{pixel:?}");
+    }
+
+    #[test]
+    fn stack_and_unstack() {
+        let a = Array::from_elem([1, 3, 4, 4], 0.5).into_dyn();
+        let b = Array::from_elem([1, 3, 4, 4], -0.5).into_dyn();
+        let batch = stack([&a, &b]).unwrap();
+        assert_eq!(batch.shape(), &[2, 3, 4, 4]);
+        assert_eq!(unstack(batch.view()), vec![a, b]);
+    }
+
     #[test]
     fn renormalize_from_0_1() {
         assert_eq!(convert_from_0_1_to_neg1_1!(0.), -1.);
@@ -291,7 +773,7 @@ mod tests {
     fn normal_image() {
         let image = DynamicImage::new(100, 110, image::ColorType::L8);
         assert_eq!(
-            center_crop_size_and_offset(Variant::Q, &image),
+            center_crop_size_and_offset(Variant::Q, &image, 2.0),
             (100, 110, 0, 0)
         );
     }
@@ -300,7 +782,7 @@ mod tests {
     fn skinny_image() {
         let image = DynamicImage::new(10, 100, image::ColorType::L8);
         assert_eq!(
-            center_crop_size_and_offset(Variant::Q, &image),
+            center_crop_size_and_offset(Variant::Q, &image, 2.0),
             (10, 10, 0, 45)
         );
     }
@@ -309,17 +791,74 @@ mod tests {
     fn wide_image() {
         let image = DynamicImage::new(101, 10, image::ColorType::L8);
         assert_eq!(
-            center_crop_size_and_offset(Variant::Q, &image),
+            center_crop_size_and_offset(Variant::Q, &image, 2.0),
             (10, 10, 45, 0)
         );
     }
 
+    #[test]
+    fn forced_crop() {
+        let image = DynamicImage::new(100, 110, image::ColorType::L8);
+        assert_eq!(
+            center_crop_size_and_offset(Variant::Q, &image, 1.0),
+            (100, 100, 0, 5)
+        );
+    }
+
+    #[test]
+    fn relaxed_limit() {
+        let image = DynamicImage::new(10, 100, image::ColorType::L8);
+        assert_eq!(
+            center_crop_size_and_offset(Variant::Q, &image, 10.0),
+            (10, 100, 0, 0)
+        );
+    }
+
     #[test]
     fn always_crop_p() {
         let image = DynamicImage::new(100, 110, image::ColorType::L8);
         assert_eq!(
-            center_crop_size_and_offset(Variant::P, &image),
+            center_crop_size_and_offset(Variant::P, &image, 2.0),
             (100, 100, 0, 5)
         );
     }
+
+    #[test]
+    fn tiles_of_panorama() {
+        assert_eq!(tiles((100, 100)), vec![(0, 0, 100)]);
+        assert_eq!(
+            tiles((300, 100)),
+            vec![(0, 0, 100), (100, 0, 100), (200, 0, 100)]
+        );
+        assert_eq!(tiles((100, 250)), vec![(0, 16, 100), (0, 133, 100)]);
+        assert_eq!(tiles((300, 0)), vec![]);
+    }
+
+    #[test]
+    fn windows_of_panorama() {
+        assert_eq!(windows((100, 100)), vec![(0, 0, 100)]);
+        assert_eq!(
+            windows((250, 100)),
+            vec![(0, 0, 100), (50, 0, 100), (100, 0, 100), (150, 0, 100)]
+        );
+        assert_eq!(
+            windows((100, 220)),
+            vec![(0, 0, 100), (0, 40, 100), (0, 80, 100), (0, 120, 100)]
+        );
+        assert_eq!(windows((0, 220)), vec![]);
+    }
+
+    #[test]
+    fn residuals_of_tiles() {
+        let residuals = vec![
+            Array::from_elem([1, 3, 256, 256], 0.1).into_dyn(),
+            Array::from_elem([1, 3, 256, 256], 0.3).into_dyn(),
+        ];
+        let padded = remove_boundary_artifact(residuals, (600, 200), Variant::Q);
+        assert_eq!(padded.shape(), &[1, 3, 256, 768]);
+        // The tiles are at 85..341 and 426..682, and everything else is the mean.
+        for (x, expected) in [(0, 0.2), (200, 0.1), (383, 0.2), (500, 0.3), (767, 0.2)] {
+            assert!((padded[[0, 0, 128, x]] - expected).abs() < 1e-4, "{x}");
+        }
+    }
 }
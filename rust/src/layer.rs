@@ -0,0 +1,156 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+//! Watermark residuals as image layers, for compositing in image editors.
+//!
+//! A layer holds the signed change the watermark makes to the red, green and blue channels of
+//! each pixel, in units of the full range of a channel, so that adding it to the image watermarks
+//! it. Layers are written by [`Residual::layer`], with the mask of the residual already applied,
+//! and composited onto an image by [`apply`].
+//!
+//! [`Residual::layer`]: crate::Residual::layer
+
+use image::{ColorType, DynamicImage, GenericImageView as _, ImageBuffer, Rgb, Rgb32FImage};
+
+use crate::{image_processing, Dither, Error, Gamut};
+
+/// The value of a [`Format::Biased16`] layer where the watermark makes no change.
+const BIAS: f32 = 32768.;
+
+/// The number of [`Format::Biased16`] levels in the full range of a channel.
+const LEVELS: f32 = 32767.;
+
+/// How a layer stores its signed values.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// 32-bit floats, which can be saved as OpenEXR.
+    #[default]
+    Float,
+    /// 16-bit integers biased by 32768, so that 32768 is no change and each level is 1/32767 of
+    /// the full range. These can be saved as TIFF or PNG.
+    Biased16,
+}
+
+impl Format {
+    /// The format of a layer with the given color type, or `None` if layers can't have it.
+    fn of(color: ColorType) -> Option<Self> {
+        match color {
+            ColorType::Rgb32F | ColorType::Rgba32F => Some(Format::Float),
+            ColorType::Rgb16 | ColorType::Rgba16 => Some(Format::Biased16),
+            _ => None,
+        }
+    }
+}
+
+/// Store the signed changes of each channel as a layer of the given format.
+pub(crate) fn encode(changes: Rgb32FImage, format: Format) -> DynamicImage {
+    match format {
+        Format::Float => changes.into(),
+        Format::Biased16 => {
+            let (width, height) = changes.dimensions();
+            ImageBuffer::<Rgb<u16>, _>::from_fn(width, height, |x, y| {
+                Rgb(changes
+                    .get_pixel(x, y)
+                    .0
+                    .map(|change| (BIAS + change * LEVELS).round().clamp(0., 65535.) as u16))
+            })
+            .into()
+        }
+    }
+}
+
+/// The signed changes to each channel stored in a layer.
+fn decode(layer: &DynamicImage) -> Result<Rgb32FImage, Error> {
+    match Format::of(layer.color()) {
+        Some(Format::Float) => Ok(layer.to_rgb32f()),
+        Some(Format::Biased16) => {
+            let layer = layer.to_rgb16();
+            let (width, height) = layer.dimensions();
+            Ok(Rgb32FImage::from_fn(width, height, |x, y| {
+                Rgb(layer
+                    .get_pixel(x, y)
+                    .0
+                    .map(|level| (level as f32 - BIAS) / LEVELS))
+            }))
+        }
+        None => Err(Error::InvalidLayer),
+    }
+}
+
+/// Composite a watermark layer onto `img`, which must have the same dimensions.
+///
+/// Each channel is clipped where the layer pushes it past black or white, as image editors do, so
+/// this is the same as applying the residual the layer was written from if it was computed with
+/// [`Gamut::Clip`]. [`Gamut::Redistribute`] spreads the overflow over nearby pixels, which may lie
+/// outside the rectangle or mask the layer covers, so it can't be expressed as a layer. `dither`
+/// decides how the result is quantized, and the watermarked image has the same [`ColorType`] as
+/// `img`. Returns [`Error::InvalidLayer`] if `layer` isn't a float or 16-bit color image.
+pub fn apply(
+    img: DynamicImage,
+    layer: &DynamicImage,
+    dither: Dither,
+) -> Result<DynamicImage, Error> {
+    if img.dimensions() != layer.dimensions() {
+        return Err(Error::DimensionMismatch);
+    }
+    let mut residual = decode(layer)?;
+    // Residual images are stored between 0 and 1, as the models' outputs are.
+    residual
+        .pixels_mut()
+        .for_each(|pixel| pixel.0 = pixel.0.map(|change| change + 0.5));
+    let color = img.color();
+    let (applied, _) = image_processing::apply_residual(img, residual.into(), None, Gamut::Clip);
+    Ok(match dither {
+        Dither::None => image_processing::to_color_type(applied, color),
+        Dither::FloydSteinberg => image_processing::dither(applied, color),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn changes() -> Rgb32FImage {
+        Rgb32FImage::from_fn(4, 3, |x, y| Rgb([x as f32 / 40., -(y as f32) / 30., 0.05]))
+    }
+
+    #[test]
+    fn formats_roundtrip() {
+        for format in [Format::Float, Format::Biased16] {
+            let layer = encode(changes(), format);
+            assert_eq!(Format::of(layer.color()), Some(format));
+            let decoded = decode(&layer).unwrap();
+            for (decoded, change) in decoded.iter().zip(changes().iter()) {
+                assert!((decoded - change).abs() < 1. / LEVELS, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn apply_changes() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 3, Rgb([100, 250, 0])));
+        let layer = encode(changes(), Format::Biased16);
+        let applied = apply(img, &layer, Dither::None).unwrap().into_rgb8();
+        assert_eq!(applied.get_pixel(2, 0).0, [113, 250, 13]);
+        assert_eq!(applied.get_pixel(0, 2).0, [100, 233, 13]);
+    }
+
+    #[test]
+    fn invalid_layers() {
+        let img = DynamicImage::new_rgb8(4, 3);
+        assert!(matches!(
+            apply(img.clone(), &DynamicImage::new_rgb8(4, 3), Dither::None),
+            Err(Error::InvalidLayer)
+        ));
+        assert!(matches!(
+            apply(img, &DynamicImage::new_rgb16(3, 4), Dither::None),
+            Err(Error::DimensionMismatch)
+        ));
+    }
+}
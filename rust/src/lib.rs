@@ -43,19 +43,41 @@
 //! ```
 use std::path::Path;
 
-use image::{DynamicImage, GenericImageView as _};
-use ort::{GraphOptimizationLevel, Session};
+use image::{DynamicImage, GenericImageView as _, GrayImage};
+use ndarray::{Array2, ArrayD, Axis};
 
 use self::{bits::Bits, image_processing::ModelImage};
 
+mod backend;
 mod bits;
+mod builder;
+mod detect;
+mod embedded;
+pub mod eval;
+mod frame;
 mod image_processing;
+pub mod layer;
+pub mod metrics;
 mod model;
+mod options;
+mod report;
+mod residual;
+mod search;
+mod strength;
 
 /// A loaded Trustmark model.
+///
+/// Use [`Trustmark::new`] to load the encoder and decoder with the default settings, or
+/// [`Trustmark::builder`] to configure the ONNX sessions and choose which models to load.
+///
+/// A `Trustmark` is `Send` and `Sync`, and all of its methods take `&self`, so a single instance
+/// can be shared between threads behind an [`Arc`](std::sync::Arc) without a mutex. The ONNX
+/// runtime supports running a session from many threads at once, and each run uses its own
+/// buffers. Concurrent runs share the session's thread pool, so when serving many requests at once
+/// it can help to lower [`TrustmarkBuilder::intra_threads`].
 pub struct Trustmark {
-    encoder: Session,
-    decoder: Session,
+    backend: Box<dyn InferenceBackend>,
+    strength: f32,
     version: Version,
     variant: Variant,
 }
@@ -64,14 +86,58 @@ pub struct Trustmark {
 pub enum Error {
     #[error("watermark is corrupt or missing")]
     CorruptWatermark,
+    #[cfg(feature = "ort")]
     #[error("onnx error: {0}")]
     Ort(#[from] ort::Error),
+    #[error("inference error: {0}")]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
     #[error("image processing error: {0}")]
     ImageProcessing(#[from] image_processing::Error),
     #[error("bits processing error: {0}")]
     Bits(bits::Error),
     #[error("invalid model variant")]
     InvalidModelVariant,
+    #[error("encoder model not loaded")]
+    EncoderNotLoaded,
+    #[error("decoder model not loaded")]
+    DecoderNotLoaded,
+    #[error("remover model not loaded")]
+    RemoverNotLoaded,
+    #[error("invalid onnx session options")]
+    InvalidSessionOptions,
+    #[error("models for this variant were not embedded")]
+    VariantNotEmbedded,
+    #[error("region is empty or outside the image")]
+    InvalidRegion,
+    #[error("images have different dimensions")]
+    DimensionMismatch,
+    #[error("no strength within the limits meets the target")]
+    StrengthTargetUnreachable,
+    #[error("invalid attack")]
+    InvalidAttack,
+    #[error("watermark did not decode at any strength up to {max_strength}")]
+    Unverified { max_strength: f32 },
+    #[error("invalid serialized residual")]
+    InvalidResidual,
+    #[error("image is not a rendition of the image the residual was computed for")]
+    ResidualMismatch,
+    #[error("layer is not a float or 16-bit color image")]
+    InvalidLayer,
+    #[error("frame buffer is too small for its dimensions, stride and layout")]
+    InvalidFrame,
+    #[error("{0} model was requested but its bytes were not given")]
+    MissingModelBytes(&'static str),
+    #[error("chase decoding can flip at most {} bits", MAX_CHASE_BITS)]
+    InvalidChaseBits,
+    #[error("aspect ratio limit must be a finite number of at least 1")]
+    InvalidAspectRatioLimit,
+    #[error(
+        "verify step must be positive and take at most {} steps, and strengths finite",
+        MAX_VERIFY_STEPS
+    )]
+    InvalidVerify,
+    #[error("frames can only be watermarked with Gamut::Clip and Dither::None")]
+    UnsupportedFrameOptions,
 }
 
 impl From<bits::Error> for Error {
@@ -83,116 +149,1016 @@ impl From<bits::Error> for Error {
     }
 }
 
-pub use bits::Version;
+#[cfg(any(test, feature = "mock"))]
+pub use backend::mock::MockBackend;
+pub use backend::{Backend, InferenceBackend};
+pub use bits::{Mode, Payload, Version};
+pub use builder::{ModelBytes, OptimizationLevel, TrustmarkBuilder};
+pub use detect::MultiVariantDecoder;
+pub use frame::{Frame, FrameMut, PixelLayout};
 pub use model::Variant;
+pub use options::{
+    validate_aspect_ratio_limit, DecodeOptions, Decoding, Dither, EncodeOptions, Gamut, Rect,
+    Region, RemoveOptions, Tiling, MAX_CHASE_BITS,
+};
+pub use report::{DecodeReport, EncodeReport};
+pub use residual::Residual;
+pub use search::{Search, Transform};
+pub use strength::{StrengthSearch, StrengthTarget, Verify, MAX_VERIFY_STEPS};
+
+/// The size of the square images the encoder runs on.
+const ENCODE_SIZE: u32 = 256;
+
+/// What the encoder changed in an image, before it is scaled by the strength.
+#[derive(Clone)]
+struct EncoderOutput {
+    /// The image, or the rectangle of it, which was encoded.
+    img: DynamicImage,
+    /// The whole input image, if only a rectangle of it was encoded.
+    outer: Option<DynamicImage>,
+    /// The encoder's output minus its input, for each input.
+    differences: Vec<ArrayD<f32>>,
+    /// The data bits of the watermark, as they should decode.
+    data: String,
+}
+
+impl EncoderOutput {
+    /// The input image, as it was before watermarking.
+    fn original(&self) -> &DynamicImage {
+        self.outer.as_ref().unwrap_or(&self.img)
+    }
+}
+
+/// A watermarked image, with the strength it was encoded at and the fraction of its watermark
+/// which was clipped.
+struct Watermarked {
+    image: DynamicImage,
+    strength: f32,
+    clipped: f32,
+}
+
+impl Watermarked {
+    /// Report on the watermarked image, measuring how much it changed from `original`.
+    fn report(self, original: &DynamicImage) -> Result<EncodeReport, Error> {
+        Ok(EncodeReport {
+            psnr: metrics::psnr(original, &self.image)?,
+            ssim: metrics::ssim(original, &self.image)?,
+            image: self.image,
+            strength: self.strength,
+            clipped: self.clipped,
+        })
+    }
+}
 
 impl Trustmark {
     /// Load a Trustmark model.
+    ///
+    /// The remover model is loaded as well if `models` contains one for this variant. It is only
+    /// needed by [`Trustmark::remove`].
     pub fn new<P: AsRef<Path>>(
         models: P,
         variant: Variant,
         version: Version,
     ) -> Result<Self, Error> {
-        let encoder = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(8)?
-            .commit_from_file(models.as_ref().join(variant.encoder_filename()))?;
-        let decoder = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(8)?
-            .commit_from_file(models.as_ref().join(variant.decoder_filename()))?;
-        Ok(Self {
-            encoder,
-            decoder,
-            version,
-            variant,
+        let remover = models.as_ref().join(variant.remover_filename()).exists();
+        Trustmark::builder(variant, version)
+            .remover(remover)
+            .build(models)
+    }
+
+    /// Load a Trustmark model from the contents of its encoder and decoder ONNX files.
+    pub fn from_memory(
+        encoder: &[u8],
+        decoder: &[u8],
+        variant: Variant,
+        version: Version,
+    ) -> Result<Self, Error> {
+        Trustmark::builder(variant, version).build_from_memory(ModelBytes {
+            encoder: Some(encoder),
+            decoder: Some(decoder),
+            remover: None,
         })
     }
 
+    /// Load a Trustmark model embedded in the crate with one of the `embed-*` features.
+    pub fn embedded(variant: Variant, version: Version) -> Result<Self, Error> {
+        Trustmark::builder(variant, version).build_embedded()
+    }
+
     /// Encode a watermark into an image.
     ///
-    /// `watermark` is a bitstring encoding the watermark identifier to encode. `img` is the image
-    /// which will be watermarked. `strength` is a number between 0 and 1 indicating how strong the
-    /// resulting watermark should be. 0.95 is a normal strength.
+    /// `watermark` is the [`Payload`] to encode. A `String` is treated as a bitstring encoding the
+    /// watermark identifier. `img` is the image which will be watermarked. `strength` is a number
+    /// between 0 and 1 indicating how strong the resulting watermark should be. 0.95 is a normal
+    /// strength.
+    ///
+    /// The watermarked image has the same [`ColorType`](image::ColorType) as `img`.
     pub fn encode(
         &self,
-        watermark: String,
+        watermark: impl Into<Payload>,
+        img: DynamicImage,
+        strength: f32,
+    ) -> Result<DynamicImage, Error> {
+        let options = EncodeOptions {
+            strength: Some(strength),
+            ..Default::default()
+        };
+        self.encode_with_options(watermark, img, &options)
+    }
+
+    /// Encode a watermark into an image with the given [`EncodeOptions`].
+    pub fn encode_with_options(
+        &self,
+        watermark: impl Into<Payload>,
+        img: DynamicImage,
+        options: &EncodeOptions,
+    ) -> Result<DynamicImage, Error> {
+        self.encode_batch([(watermark, img)], options)?
+            .pop()
+            .expect("one result per image")
+    }
+
+    /// Encode a watermark into an image with the given [`EncodeOptions`], and report how much the
+    /// image changed and how much of the watermark was lost to clipping.
+    ///
+    /// Measuring the change takes about as long as encoding a large image, so this is slower than
+    /// [`Trustmark::encode_with_options`].
+    pub fn encode_with_report(
+        &self,
+        watermark: impl Into<Payload>,
         img: DynamicImage,
+        options: &EncodeOptions,
+    ) -> Result<EncodeReport, Error> {
+        self.encode_batch_with_report([(watermark, img)], options)?
+            .pop()
+            .expect("one result per image")
+    }
+
+    /// Compute the change that encoding a watermark with the given [`EncodeOptions`] makes to an
+    /// image, to apply to any number of renditions of it with [`Residual::apply`].
+    ///
+    /// Applying the residual to `img` itself gives the same image as
+    /// [`Trustmark::encode_with_options`]. The residual is computed at the strength found by the
+    /// [`EncodeOptions::strength_search`] if there is one, and the mask or rectangle of the
+    /// [`EncodeOptions::region`] is kept with it.
+    pub fn compute_residual(
+        &self,
+        watermark: impl Into<Payload>,
+        img: DynamicImage,
+        options: &EncodeOptions,
+    ) -> Result<Residual, Error> {
+        let output = self
+            .encoder_outputs([(watermark, img)], options)?
+            .pop()
+            .expect("one result per image")?;
+        let strength = match &options.strength_search {
+            Some(search) => {
+                self.search_strength(output.clone(), options, search)?
+                    .strength
+            }
+            None => options.strength.unwrap_or(self.strength),
+        };
+        Ok(self.residual(&output, strength, options))
+    }
+
+    /// Encode a watermark in place into a frame, such as one from a video decoder or camera, with
+    /// the given [`EncodeOptions`].
+    ///
+    /// The encoder runs on a copy of the frame scaled down to the size the models run at, and
+    /// its residual is upscaled straight into the frame as by [`Residual::apply_frame`], so the
+    /// frame is never copied at its full size. A [`Region::Rect`] is in the frame's pixels.
+    ///
+    /// Returns [`Error::UnsupportedFrameOptions`] for [`Gamut::Redistribute`] and
+    /// [`Dither::FloydSteinberg`], which need a copy of the frame.
+    pub fn encode_frame(
+        &self,
+        watermark: impl Into<Payload>,
+        frame: &mut FrameMut<'_>,
+        options: &EncodeOptions,
+    ) -> Result<(), Error> {
+        if options.gamut != Gamut::Clip || options.dither != Dither::None {
+            return Err(Error::UnsupportedFrameOptions);
+        }
+        let proxy = frame.as_frame().proxy();
+        let options = EncodeOptions {
+            region: match &options.region {
+                Some(Region::Rect(rect)) => Some(Region::Rect(residual::scale(
+                    rect,
+                    frame.dimensions(),
+                    proxy.dimensions(),
+                ))),
+                region => region.clone(),
+            },
+            ..options.clone()
+        };
+        self.compute_residual(watermark, proxy, &options)?
+            .apply_frame(frame)
+    }
+
+    /// Encode watermarks into a batch of images with a single run of the encoder.
+    ///
+    /// Each image is paired with the watermark to encode into it, and all are encoded with the same
+    /// `options`. The returned `Vec` has a result for each image, in order. The outer error is only
+    /// returned if the encoder itself fails, which fails the whole batch.
+    pub fn encode_batch<W, I>(
+        &self,
+        batch: I,
+        options: &EncodeOptions,
+    ) -> Result<Vec<Result<DynamicImage, Error>>, Error>
+    where
+        W: Into<Payload>,
+        I: IntoIterator<Item = (W, DynamicImage)>,
+    {
+        Ok(self
+            .encode_watermarked(batch, options)?
+            .into_iter()
+            .map(|watermarked| Ok(watermarked?.image))
+            .collect())
+    }
+
+    /// Encode watermarks into a batch of images like [`Trustmark::encode_batch`], reporting on
+    /// each image like [`Trustmark::encode_with_report`].
+    pub fn encode_batch_with_report<W, I>(
+        &self,
+        batch: I,
+        options: &EncodeOptions,
+    ) -> Result<Vec<Result<EncodeReport, Error>>, Error>
+    where
+        W: Into<Payload>,
+        I: IntoIterator<Item = (W, DynamicImage)>,
+    {
+        let batch: Vec<_> = batch.into_iter().collect();
+        let originals: Vec<_> = batch.iter().map(|(_, img)| img.clone()).collect();
+        Ok(self
+            .encode_watermarked(batch, options)?
+            .into_iter()
+            .zip(originals)
+            .map(|(encoded, original)| encoded?.report(&original))
+            .collect())
+    }
+
+    /// Encode watermarks into a batch of images, returning each watermarked image with the
+    /// strength it was encoded at and the fraction of its watermark which was clipped.
+    fn encode_watermarked<W, I>(
+        &self,
+        batch: I,
+        options: &EncodeOptions,
+    ) -> Result<Vec<Result<Watermarked, Error>>, Error>
+    where
+        W: Into<Payload>,
+        I: IntoIterator<Item = (W, DynamicImage)>,
+    {
+        let strength = options.strength.unwrap_or(self.strength);
+        Ok(self
+            .encoder_outputs(batch, options)?
+            .into_iter()
+            .map(|output| match &options.strength_search {
+                Some(search) => self.search_strength(output?, options, search),
+                None => self.apply_strength(output?, strength, options),
+            })
+            .collect())
+    }
+
+    /// Run the encoder on a batch of images, returning what it changed in each.
+    fn encoder_outputs<W, I>(
+        &self,
+        batch: I,
+        options: &EncodeOptions,
+    ) -> Result<Vec<Result<EncoderOutput, Error>>, Error>
+    where
+        W: Into<Payload>,
+        I: IntoIterator<Item = (W, DynamicImage)>,
+    {
+        options.validate()?;
+        let aspect_ratio_limit = options.aspect_ratio_limit;
+
+        // Images which can't be prepared keep their error, and are left out of the batch.
+        let prepared: Vec<_> = batch
+            .into_iter()
+            .map(|(watermark, img)| -> Result<_, Error> {
+                // A rectangle is encoded as if it were the whole image, then pasted back.
+                let (img, outer) = match &options.region {
+                    Some(Region::Rect(rect)) => (crop(&img, rect)?, Some(img)),
+                    _ => (img, None),
+                };
+                let tiled = options.tiling == Tiling::Tiled
+                    && image_processing::is_center_cropped(
+                        self.variant,
+                        img.dimensions(),
+                        aspect_ratio_limit,
+                    );
+                let tiles = if tiled {
+                    image_processing::tiles(img.dimensions())
+                } else {
+                    Vec::new()
+                };
+                let input_imgs =
+                    self.model_inputs(ENCODE_SIZE, &img, aspect_ratio_limit, &tiles)?;
+                let bits = Bits::apply_error_correction_and_schema(
+                    watermark.into().to_bitstring()?,
+                    self.version,
+                )?;
+                let data = bits.clone().get_data();
+                Ok((img, outer, input_imgs, bits.into(), data))
+            })
+            .collect();
+
+        let mut output_imgs = Vec::new().into_iter();
+        if prepared.iter().any(Result::is_ok) {
+            let input_imgs = image_processing::stack(
+                prepared
+                    .iter()
+                    .flatten()
+                    .flat_map(|(_, _, inputs, _, _)| inputs),
+            )?;
+            // Every tile of an image is encoded with the same bits.
+            let bits: Vec<_> = prepared
+                .iter()
+                .flatten()
+                .flat_map(|(_, _, inputs, bits, _)| inputs.iter().map(|_| Array2::view(bits)))
+                .collect();
+            let bits =
+                ndarray::concatenate(Axis(0), &bits).map_err(image_processing::Error::from)?;
+            let count = input_imgs.shape()[0];
+            let output_imgs_batch = self.backend.encode(input_imgs, bits.into_dyn())?;
+            check_batch("encoder", &output_imgs_batch, count)?;
+            output_imgs = image_processing::unstack(output_imgs_batch.view()).into_iter();
+        }
+
+        Ok(prepared
+            .into_iter()
+            .map(|prepared| {
+                let (img, outer, input_imgs, _, data) = prepared?;
+                let differences = input_imgs
+                    .into_iter()
+                    .zip(output_imgs.by_ref())
+                    .map(|(input_img, output_img)| output_img - input_img)
+                    .collect();
+                Ok(EncoderOutput {
+                    img,
+                    outer,
+                    differences,
+                    data,
+                })
+            })
+            .collect())
+    }
+
+    /// Watermark an image with what the encoder changed in it, scaled by `strength`.
+    fn apply_strength(
+        &self,
+        output: EncoderOutput,
         strength: f32,
+        options: &EncodeOptions,
+    ) -> Result<Watermarked, Error> {
+        let residual = self.residual(&output, strength, options);
+        let (image, clipped) = residual.apply_clipped(output.outer.unwrap_or(output.img))?;
+        Ok(Watermarked {
+            image,
+            strength,
+            clipped,
+        })
+    }
+
+    /// What the encoder changed in an image, scaled by `strength` and ready to be applied.
+    fn residual(&self, output: &EncoderOutput, strength: f32, options: &EncodeOptions) -> Residual {
+        let residuals = output
+            .differences
+            .iter()
+            .map(|difference| {
+                // Need to calculate and apply the residual.
+                let residual = (self.variant.strength_multiplier() * strength) * difference;
+
+                // Residual should be small perturbations.
+                residual.clamp(-0.2, 0.2)
+            })
+            .collect();
+
+        let (rect, mask) = match &options.region {
+            Some(Region::Rect(rect)) => (Some(*rect), None),
+            Some(Region::Mask(mask)) => (None, Some(mask.clone())),
+            None => (None, None),
+        };
+        Residual {
+            variant: self.variant,
+            aspect_ratio_limit: options.aspect_ratio_limit,
+            strength,
+            dimensions: output.original().dimensions(),
+            rect,
+            mask,
+            gamut: options.gamut,
+            dither: options.dither,
+            residual: self.combine_residuals(
+                output.img.dimensions(),
+                options.aspect_ratio_limit,
+                residuals,
+            ),
+        }
+    }
+
+    /// Watermark an image at the strength closest to the target of `search`.
+    fn search_strength(
+        &self,
+        output: EncoderOutput,
+        options: &EncodeOptions,
+        search: &StrengthSearch,
+    ) -> Result<Watermarked, Error> {
+        search.run(|strength| {
+            let watermarked = self.apply_strength(output.clone(), strength, options)?;
+            let meets = match search.target {
+                StrengthTarget::Psnr(psnr) => {
+                    metrics::psnr(output.original(), &watermarked.image)? >= psnr
+                }
+                StrengthTarget::Jpeg(quality) => {
+                    self.reads_back(&watermarked.image, &output.data, Some(quality), options)?
+                }
+            };
+            Ok(meets.then_some(watermarked))
+        })
+    }
+
+    /// Encode a watermark into an image with the given [`EncodeOptions`], then check that it
+    /// decodes, raising the strength until it does as set out by `verify`.
+    ///
+    /// The first strength tried is [`EncodeOptions::strength`]; a
+    /// [`EncodeOptions::strength_search`] isn't used. The encoder only runs once, and the decoder
+    /// runs once for each strength tried, so the decoder must be loaded. The strength which
+    /// decoded is reported in [`EncodeReport::strength`], and [`Error::Unverified`] is returned if
+    /// none up to and including [`Verify::max_strength`] did.
+    pub fn encode_verified(
+        &self,
+        watermark: impl Into<Payload>,
+        img: DynamicImage,
+        options: &EncodeOptions,
+        verify: &Verify,
+    ) -> Result<EncodeReport, Error> {
+        let strengths = verify.strengths(options.strength.unwrap_or(self.strength))?;
+        let output = self
+            .encoder_outputs([(watermark, img)], options)?
+            .pop()
+            .expect("one result per image")?;
+        for strength in strengths {
+            let watermarked = self.apply_strength(output.clone(), strength, options)?;
+            if self.reads_back(
+                &watermarked.image,
+                &output.data,
+                verify.jpeg_quality,
+                options,
+            )? {
+                return watermarked.report(output.original());
+            }
+        }
+        Err(Error::Unverified {
+            max_strength: verify.max_strength,
+        })
+    }
+
+    /// Whether the watermark of an image encoded with `options` decodes to `data`, after a JPEG
+    /// round-trip at `jpeg_quality` if there is one.
+    fn reads_back(
+        &self,
+        image: &DynamicImage,
+        data: &str,
+        jpeg_quality: Option<u8>,
+        options: &EncodeOptions,
+    ) -> Result<bool, Error> {
+        let decode_options = DecodeOptions {
+            aspect_ratio_limit: options.aspect_ratio_limit,
+            tiling: options.tiling,
+            region: match &options.region {
+                Some(Region::Rect(rect)) => Some(*rect),
+                _ => None,
+            },
+            ..Default::default()
+        };
+        let image = match jpeg_quality {
+            Some(quality) => image_processing::jpeg_roundtrip(image, quality)?,
+            None => image.clone(),
+        };
+        match self.decode_with_options(image, &decode_options) {
+            Ok(report) => Ok(report.data == data),
+            Err(Error::CorruptWatermark) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Remove a watermark from an image.
+    ///
+    /// This requires the remover model to have been loaded. `img` is the watermarked image.
+    /// `strength` is a number indicating how strongly the watermark should be removed. 1.0 is a
+    /// normal strength. The cleaned image has the same [`ColorType`](image::ColorType) as `img`.
+    pub fn remove(&self, img: DynamicImage, strength: f32) -> Result<DynamicImage, Error> {
+        let options = RemoveOptions {
+            strength,
+            ..Default::default()
+        };
+        self.remove_with_options(img, &options)
+    }
+
+    /// Remove a watermark from an image with the given [`RemoveOptions`].
+    pub fn remove_with_options(
+        &self,
+        img: DynamicImage,
+        options: &RemoveOptions,
     ) -> Result<DynamicImage, Error> {
-        let (original_width, original_height) = img.dimensions();
-        let aspect_ratio = original_width as f32 / original_height as f32;
-
-        // the image is always encoded with size 256x256
-        let encode_size = 256;
-
-        let input_img: ort::Value<ort::TensorValueType<f32>> =
-            ModelImage(encode_size, self.variant, img.clone()).try_into()?;
-        let bits: ort::Value<ort::TensorValueType<f32>> =
-            Bits::apply_error_correction_and_schema(watermark, self.version)?.into();
-        let outputs = self.encoder.run(ort::inputs![
-            "onnx::Concat_0" => input_img,
-            "onnx::Gemm_1" => bits,
-        ]?)?;
-        let output_img = outputs["image"].try_extract_tensor::<f32>()?.to_owned();
-
-        // Need to calculate and apply the residual.
-        let input_img: ort::Value<ort::TensorValueType<f32>> =
-            ModelImage(encode_size, self.variant, img.clone()).try_into()?;
+        options.validate()?;
+
+        // the image is always processed with size 256x256
+        let remove_size = 256;
+
+        let RemoveOptions {
+            strength,
+            aspect_ratio_limit,
+        } = *options;
+
+        let input_img = ArrayD::try_from(ModelImage(
+            remove_size,
+            self.variant,
+            aspect_ratio_limit,
+            img.clone(),
+        ))?;
+        let output_img = self.backend.remove(input_img.clone())?;
+        if output_img.shape() != input_img.shape() {
+            return Err(Error::Backend(
+                format!(
+                    "remover returned shape {:?} for an input of shape {:?}",
+                    output_img.shape(),
+                    input_img.shape()
+                )
+                .into(),
+            ));
+        }
+
+        // The residual takes the image from the watermarked input to the cleaned output.
         let residual = (self.variant.strength_multiplier() * strength)
-            * (output_img - input_img.try_extract_tensor::<f32>()?);
-
-        // Residual should be small perturbations.
-        let mut residual = residual.clamp(-0.2, 0.2);
-        if (self.variant == Variant::Q && !(0.5..=2.0).contains(&aspect_ratio))
-            || self.variant == Variant::P
-        {
-            residual = image_processing::remove_boundary_artifact(
-                residual,
-                (original_width as usize, original_height as usize),
-                self.variant,
-            );
+            * (output_img.clamp(-1., 1.) - input_img);
+
+        let color = img.color();
+        let (removed, _) = self.apply_residual(
+            img,
+            remove_size,
+            aspect_ratio_limit,
+            vec![residual],
+            None,
+            Gamut::Clip,
+        )?;
+        Ok(image_processing::to_color_type(removed, color))
+    }
+
+    /// The size of the square images the decoder runs on.
+    fn decode_size(&self) -> u32 {
+        // P variant has a smaller decode size
+        if self.variant == Variant::P {
+            224
+        } else {
+            256
         }
+    }
+
+    /// Prepare `img` to be passed to a model with inputs of `size`x`size`.
+    ///
+    /// Without `tiles`, this is the whole image, or its center square if it is center-cropped.
+    /// Otherwise it is each of the `(x, y, size)` squares in `tiles`.
+    fn model_inputs(
+        &self,
+        size: u32,
+        img: &DynamicImage,
+        aspect_ratio_limit: f32,
+        tiles: &[(u32, u32, u32)],
+    ) -> Result<Vec<ArrayD<f32>>, Error> {
+        if tiles.is_empty() {
+            let input = ModelImage(size, self.variant, aspect_ratio_limit, img.clone());
+            return Ok(vec![ArrayD::try_from(input)?]);
+        }
+        tiles
+            .iter()
+            .map(|&(x, y, side)| {
+                let tile = img.crop_imm(x, y, side, side);
+                Ok(ArrayD::try_from(ModelImage(
+                    size,
+                    self.variant,
+                    aspect_ratio_limit,
+                    tile,
+                ))?)
+            })
+            .collect()
+    }
+
+    /// Upscale `size`x`size` model residuals and apply them to `img`.
+    ///
+    /// The residuals are combined as by [`Trustmark::combine_residuals`]. The residual is scaled by
+    /// the `mask`, if there is one.
+    fn apply_residual(
+        &self,
+        img: DynamicImage,
+        size: u32,
+        aspect_ratio_limit: f32,
+        residuals: Vec<ArrayD<f32>>,
+        mask: Option<&GrayImage>,
+        gamut: Gamut,
+    ) -> Result<(DynamicImage, f32), Error> {
+        let residual = self.combine_residuals(img.dimensions(), aspect_ratio_limit, residuals);
+        let ModelImage(_, _, _, residual) =
+            (size, self.variant, aspect_ratio_limit, residual).try_into()?;
 
-        let ModelImage(_, _, residual) = (encode_size, self.variant, residual).try_into()?;
+        Ok(image_processing::apply_residual(img, residual, mask, gamut))
+    }
 
-        Ok(image_processing::apply_residual(img, residual))
+    /// Combine the model residuals of an image of the given dimensions into one residual with the
+    /// aspect ratio of the image.
+    ///
+    /// If the image was center-cropped for the model, the residuals of its center square or of its
+    /// tiles are padded out to cover the whole image. Otherwise there is a single residual for the
+    /// whole image.
+    fn combine_residuals(
+        &self,
+        (width, height): (u32, u32),
+        aspect_ratio_limit: f32,
+        mut residuals: Vec<ArrayD<f32>>,
+    ) -> ArrayD<f32> {
+        if image_processing::is_center_cropped(self.variant, (width, height), aspect_ratio_limit) {
+            image_processing::remove_boundary_artifact(
+                residuals,
+                (width as usize, height as usize),
+                self.variant,
+            )
+        } else {
+            residuals.pop().expect("one residual for the whole image")
+        }
     }
 
     /// Decode a watermark from an image.
     pub fn decode(&self, img: DynamicImage) -> Result<String, Error> {
-        // P variant has a smaller decode size
-        let decode_size = if self.variant == Variant::P { 224 } else { 256 };
+        Ok(self.decode_detailed(img)?.data)
+    }
+
+    /// Decode a watermark from an image, interpreting its data as a [`Payload`] of the given
+    /// `mode`.
+    pub fn decode_payload(&self, img: DynamicImage, mode: Mode) -> Result<Payload, Error> {
+        Ok(self.decode_detailed(img)?.payload(mode))
+    }
+
+    /// Decode a watermark from an image, returning a [`DecodeReport`] describing the error
+    /// correction which was needed and the decoder's confidence in each bit.
+    pub fn decode_detailed(&self, img: DynamicImage) -> Result<DecodeReport, Error> {
+        self.decode_with_options(img, &DecodeOptions::default())
+    }
+
+    /// Decode a watermark from an image with the given [`DecodeOptions`].
+    pub fn decode_with_options(
+        &self,
+        img: DynamicImage,
+        options: &DecodeOptions,
+    ) -> Result<DecodeReport, Error> {
+        if let Some(search) = &options.search {
+            return self.search(img, options, search);
+        }
+        self.decode_batch([img], options)?
+            .pop()
+            .expect("one result per image")
+    }
+
+    /// Decode a watermark from a frame, such as one from a video decoder or camera, with the given
+    /// [`DecodeOptions`].
+    ///
+    /// The decoder runs on a copy of the frame scaled down to the size the models run at, so the
+    /// frame is never copied at its full size. The [`DecodeOptions::region`] and the
+    /// [`DecodeReport::transform`] are in the frame's pixels.
+    pub fn decode_frame(
+        &self,
+        frame: &Frame<'_>,
+        options: &DecodeOptions,
+    ) -> Result<DecodeReport, Error> {
+        let proxy = frame.proxy();
+        let (from, to) = (frame.dimensions(), proxy.dimensions());
+        let region = options.region;
+        let options = DecodeOptions {
+            region: region.map(|rect| residual::scale(&rect, from, to)),
+            ..options.clone()
+        };
+        let mut report = self.decode_with_options(proxy, &options)?;
+        // The window is offset from the corner of the region, which is mapped back exactly.
+        let corner =
+            |rect: Option<Rect>| rect.map_or((0, 0), |rect| (rect.x as i32, rect.y as i32));
+        let (proxy_x, proxy_y) = corner(options.region);
+        let (x, y) = corner(region);
+        report.transform = report.transform.map(|transform| {
+            let scale = |value: i32, from: u32, to: u32| {
+                (value as f64 * to as f64 / from as f64).round() as i32
+            };
+            Transform {
+                x: x + scale(transform.x - proxy_x, to.0, from.0),
+                y: y + scale(transform.y - proxy_y, to.1, from.1),
+                width: scale(transform.width as i32, to.0, from.0) as u32,
+                height: scale(transform.height as i32, to.1, from.1) as u32,
+                ..transform
+            }
+        });
+        Ok(report)
+    }
+
+    /// Decode a watermark from the first of the `search` candidates it is found in.
+    fn search(
+        &self,
+        img: DynamicImage,
+        options: &DecodeOptions,
+        search: &Search,
+    ) -> Result<DecodeReport, Error> {
+        let (img, x, y) = match &options.region {
+            Some(rect) => (crop(&img, rect)?, rect.x as i32, rect.y as i32),
+            None => (img, 0, 0),
+        };
+        let options = DecodeOptions {
+            search: None,
+            region: None,
+            ..options.clone()
+        };
+        match self.decode_with_options(img.clone(), &options) {
+            Err(Error::CorruptWatermark) => {}
+            result => return result,
+        }
+
+        for transforms in search.candidates(img.dimensions()) {
+            let windows = transforms.iter().map(|transform| transform.apply(&img));
+            let reports = self.decode_batch(windows, &options)?;
+            for (report, transform) in reports.into_iter().zip(transforms) {
+                match report {
+                    Ok(report)
+                        if !report.version_fallback
+                            && report.corrected_bits <= search.max_corrected_bits =>
+                    {
+                        // The window is in the region, so it is offset to be in the image.
+                        return Ok(DecodeReport {
+                            transform: Some(Transform {
+                                x: x + transform.x,
+                                y: y + transform.y,
+                                ..transform
+                            }),
+                            ..report
+                        });
+                    }
+                    Ok(_) | Err(Error::CorruptWatermark) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Err(Error::CorruptWatermark)
+    }
+
+    /// Decode watermarks from a batch of images with a single run of the decoder.
+    ///
+    /// The returned `Vec` has a result for each image, in order. The outer error is only returned
+    /// if the decoder itself fails, which fails the whole batch.
+    ///
+    /// With a [`DecodeOptions::search`], each image is searched separately instead, and errors of
+    /// the decoder are returned for that image only.
+    pub fn decode_batch(
+        &self,
+        imgs: impl IntoIterator<Item = DynamicImage>,
+        options: &DecodeOptions,
+    ) -> Result<Vec<Result<DecodeReport, Error>>, Error> {
+        options.validate()?;
+        if options.search.is_some() {
+            return Ok(imgs
+                .into_iter()
+                .map(|img| self.decode_with_options(img, options))
+                .collect());
+        }
+
+        let decode_size = self.decode_size();
+
+        // Images which can't be prepared keep their error, and are left out of the batch.
+        let prepared: Vec<Result<Vec<ArrayD<f32>>, Error>> = imgs
+            .into_iter()
+            .map(|img| {
+                let img = match &options.region {
+                    Some(rect) => crop(&img, rect)?,
+                    None => img,
+                };
+                let (width, height) = img.dimensions();
+                let mut inputs =
+                    self.model_inputs(decode_size, &img, options.aspect_ratio_limit, &[])?;
+                // A tiled watermark may be in any of the tiles, or anywhere else if the image
+                // was cropped. Images within the aspect ratio limit are encoded whole, as they
+                // are when encoding.
+                let tiled = options.tiling == Tiling::Tiled
+                    && image_processing::is_center_cropped(
+                        self.variant,
+                        (width, height),
+                        options.aspect_ratio_limit,
+                    );
+                if tiled {
+                    let mut squares = image_processing::tiles((width, height));
+                    squares.extend(image_processing::windows((width, height)));
+                    squares.sort_unstable();
+                    squares.dedup();
+                    inputs.extend(self.model_inputs(
+                        decode_size,
+                        &img,
+                        options.aspect_ratio_limit,
+                        &squares,
+                    )?);
+                }
+                Ok(inputs)
+            })
+            .collect();
+
+        let mut watermarks = Vec::new().into_iter();
+        if prepared.iter().any(Result::is_ok) {
+            let imgs = image_processing::stack(prepared.iter().flatten().flatten())?;
+            let count = imgs.shape()[0];
+            let watermarks_batch = self.backend.decode(imgs)?;
+            check_batch("decoder", &watermarks_batch, count)?;
+            watermarks = image_processing::unstack(watermarks_batch.view()).into_iter();
+        }
+
+        Ok(prepared
+            .into_iter()
+            .map(|prepared| {
+                let count = prepared?.len();
+                let watermarks: Vec<_> = watermarks.by_ref().take(count).collect();
+                if let [watermark] = &watermarks[..] {
+                    return self.decode_report(watermark.clone(), options.decoding);
+                }
+
+                // Vote on each bit with the mean of the logits, falling back to the best square.
+                let vote = watermarks
+                    .iter()
+                    .fold(ArrayD::zeros(watermarks[0].shape()), |vote, watermark| {
+                        vote + watermark
+                    })
+                    / count as f32;
+                detect::best(
+                    std::iter::once(vote)
+                        .chain(watermarks)
+                        .map(|watermark| self.decode_report(watermark, options.decoding)),
+                )
+            })
+            .collect())
+    }
+
+    /// Correct the bits of a decoder output.
+    fn decode_report(
+        &self,
+        watermark: ArrayD<f32>,
+        decoding: Decoding,
+    ) -> Result<DecodeReport, Error> {
+        let logits = watermark.iter().copied().collect();
+        let (watermark, correction) = match decoding {
+            Decoding::Hard => Bits::from_logits(watermark)?,
+            Decoding::Chase { bits } => Bits::from_logits_chase(watermark, bits)?,
+        };
+        Ok(DecodeReport {
+            version: watermark.get_version(),
+            variant: self.variant,
+            data: watermark.get_data(),
+            corrected_bits: correction.bitflips,
+            version_fallback: correction.version_fallback,
+            logits,
+            transform: None,
+        })
+    }
+}
+
+/// Check that a model returned one output for each of the `count` inputs it was given.
+fn check_batch(model: &str, outputs: &ArrayD<f32>, count: usize) -> Result<(), Error> {
+    if outputs.shape().first() != Some(&count) {
+        return Err(Error::Backend(
+            format!(
+                "{model} returned shape {:?} for a batch of {count}",
+                outputs.shape()
+            )
+            .into(),
+        ));
+    }
+    Ok(())
+}
 
-        let img: ort::Value<ort::TensorValueType<f32>> =
-            ModelImage(decode_size, self.variant, img).try_into()?;
-        let outputs = self.decoder.run(ort::inputs![
-            "image" => img,
-        ]?)?;
-        let watermark = outputs["output"].try_extract_tensor::<f32>()?.to_owned();
-        let watermark: Bits = watermark.try_into()?;
-        Ok(watermark.get_data())
+/// Crop `img` to `rect`, which must be a non-empty part of it.
+fn crop(img: &DynamicImage, rect: &Rect) -> Result<DynamicImage, Error> {
+    let (width, height) = img.dimensions();
+    if rect.width == 0
+        || rect.height == 0
+        || rect.x.saturating_add(rect.width) > width
+        || rect.y.saturating_add(rect.height) > height
+    {
+        return Err(Error::InvalidRegion);
     }
+    Ok(img.crop_imm(rect.x, rect.y, rect.width, rect.height))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
     use super::*;
 
+    /// The watermark the tests encode, unless they need a particular payload.
+    const WATERMARK: &str = "1011011110011000111111000000011111011111011100000110110110111";
+
+    /// The image most tests watermark.
+    fn ghost() -> DynamicImage {
+        image::open("../images/ghost.png").unwrap()
+    }
+
     #[test]
     fn loading_models() {
         Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
     }
 
+    /// Every backend enabled by the crate's features.
+    const BACKENDS: &[Backend] = &[
+        #[cfg(feature = "ort")]
+        Backend::Ort,
+        #[cfg(feature = "tract")]
+        Backend::Tract,
+    ];
+
     fn roundtrip(path: impl AsRef<Path>) {
+        for &backend in BACKENDS {
+            let tm = Trustmark::builder(Variant::Q, Version::Bch5)
+                .backend(backend)
+                .build("./models")
+                .unwrap();
+            let input = image::open(path.as_ref()).unwrap();
+            let watermark = WATERMARK.to_owned();
+            let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+            encoded.to_rgba8().save("./test.png").unwrap();
+            let input = image::open("./test.png").unwrap();
+            let decoded = tm.decode(input).unwrap();
+            assert_eq!(watermark, decoded, "{backend:?}");
+        }
+    }
+
+    /// Every color type images are encoded in.
+    const COLOR_TYPES: [image::ColorType; 10] = [
+        image::ColorType::L8,
+        image::ColorType::La8,
+        image::ColorType::Rgb8,
+        image::ColorType::Rgba8,
+        image::ColorType::L16,
+        image::ColorType::La16,
+        image::ColorType::Rgb16,
+        image::ColorType::Rgba16,
+        image::ColorType::Rgb32F,
+        image::ColorType::Rgba32F,
+    ];
+
+    #[test]
+    fn roundtrip_color_types() {
         let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
-        let input = image::open(path.as_ref()).unwrap();
-        let watermark = "1011011110011000111111000000011111011111011100000110110110111".to_owned();
-        let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
-        encoded.to_rgba8().save("./test.png").unwrap();
-        let input = image::open("./test.png").unwrap();
-        let decoded = tm.decode(input).unwrap();
-        assert_eq!(watermark, decoded);
+        let input = ghost();
+        let watermark = WATERMARK.to_owned();
+        for color in COLOR_TYPES {
+            let input = image_processing::to_color_type(input.clone(), color);
+            let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+            assert_eq!(encoded.color(), color);
+            assert_eq!(tm.decode(encoded).unwrap(), watermark, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn loading_models_from_memory() {
+        let encoder = std::fs::read("./models/encoder_Q.onnx").unwrap();
+        let decoder: std::sync::Arc<[u8]> =
+            std::fs::read("./models/decoder_Q.onnx").unwrap().into();
+        Trustmark::from_memory(&encoder, &decoder, Variant::Q, Version::Bch5).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "embed-b"))]
+    fn not_embedded() {
+        let err = Trustmark::embedded(Variant::B, Version::Bch5)
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::VariantNotEmbedded));
+    }
+
+    #[test]
+    fn missing_model_bytes() {
+        let err = Trustmark::builder(Variant::Q, Version::Bch5)
+            .build_from_memory(ModelBytes {
+                encoder: Some(&[]),
+                ..Default::default()
+            })
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::MissingModelBytes("decoder")));
+    }
+
+    #[test]
+    fn decoder_only() {
+        let tm = Trustmark::builder(Variant::Q, Version::Bch5)
+            .intra_threads(2)
+            .encoder(false)
+            .build("./models")
+            .unwrap();
+        let input = ghost();
+        let err = tm.encode("0".to_owned(), input, 0.95).unwrap_err();
+        assert!(matches!(err, Error::EncoderNotLoaded));
     }
 
     #[test]
@@ -204,4 +1170,887 @@ mod tests {
     fn roundtrip_ufo() {
         roundtrip("../images/ufo_240.jpg");
     }
+
+    #[test]
+    fn decode_detailed_ghost() {
+        let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+        let input = ghost();
+        let watermark = WATERMARK.to_owned();
+        let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+        let report = tm.decode_detailed(encoded).unwrap();
+        assert_eq!(report.data, watermark);
+        assert_eq!(report.version, Version::Bch5);
+        assert_eq!(report.logits.len(), 100);
+        assert!(report.min_confidence() > 0.);
+    }
+
+    #[test]
+    fn roundtrip_forced_crop() {
+        let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+        let input = image::open("../images/ufo_240.jpg").unwrap();
+        let watermark = WATERMARK.to_owned();
+        let encode_options = EncodeOptions {
+            aspect_ratio_limit: 1.0,
+            ..Default::default()
+        };
+        let encoded = tm
+            .encode_with_options(watermark.clone(), input, &encode_options)
+            .unwrap();
+        let decode_options = DecodeOptions {
+            aspect_ratio_limit: 1.0,
+            ..Default::default()
+        };
+        let decoded = tm.decode_with_options(encoded, &decode_options).unwrap();
+        assert_eq!(watermark, decoded.data);
+    }
+
+    #[test]
+    #[ignore = "needs models/remover_Q.onnx, exported from the Python remover checkpoint, which \
+                `cargo xtask fetch-models` doesn't fetch"]
+    fn remove_ghost() {
+        let tm = Trustmark::builder(Variant::Q, Version::Bch5)
+            .remover(true)
+            .build("./models")
+            .unwrap();
+        let input = ghost();
+        let watermark = WATERMARK.to_owned();
+        let encoded = tm.encode(watermark, input, 0.95).unwrap();
+        let removed = tm.remove(encoded, 1.0).unwrap();
+        assert!(matches!(tm.decode(removed), Err(Error::CorruptWatermark)));
+    }
+
+    #[test]
+    fn roundtrip_text() {
+        let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+        let input = ghost();
+        let watermark = Payload::Text("ghost".to_owned());
+        let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+        let decoded = tm.decode_payload(encoded, Mode::Text).unwrap();
+        assert_eq!(watermark, decoded);
+    }
+
+    #[test]
+    fn roundtrip_batch() {
+        let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+        let ghost = ghost();
+        let ufo = image::open("../images/ufo_240.jpg").unwrap();
+        let watermark = WATERMARK.to_owned();
+        let batch = vec![
+            (Payload::Binary(watermark.clone()), ghost),
+            (Payload::Text("héllo".to_owned()), ufo.clone()),
+            (Payload::Text("ufo".to_owned()), ufo),
+        ];
+        let encoded = tm.encode_batch(batch, &EncodeOptions::default()).unwrap();
+        assert_eq!(encoded.len(), 3);
+        assert!(matches!(encoded[1], Err(Error::Bits(_))));
+
+        let encoded: Vec<_> = encoded.into_iter().flatten().collect();
+        let decoded = tm.decode_batch(encoded, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded[0].as_ref().unwrap().data, watermark);
+        assert_eq!(
+            decoded[1].as_ref().unwrap().payload(Mode::Text),
+            Payload::Text("ufo".to_owned())
+        );
+    }
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Trustmark>();
+        assert_send_sync::<MultiVariantDecoder>();
+    }
+
+    #[test]
+    fn concurrent_decode() {
+        let tm = Trustmark::new("./models", Variant::Q, Version::Bch5).unwrap();
+        let watermark = WATERMARK.to_owned();
+        let images: Vec<_> = ["../images/ghost.png", "../images/ufo_240.jpg"]
+            .into_iter()
+            .map(|path| {
+                let input = image::open(path).unwrap();
+                tm.encode(watermark.clone(), input, 0.95).unwrap()
+            })
+            .collect();
+        let expected: Vec<_> = images
+            .iter()
+            .map(|img| tm.decode_detailed(img.clone()).unwrap().logits)
+            .collect();
+
+        let tm = std::sync::Arc::new(tm);
+        let images = std::sync::Arc::new(images);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let tm = tm.clone();
+                let images = images.clone();
+                std::thread::spawn(move || {
+                    (0..4)
+                        .flat_map(|_| images.iter())
+                        .map(|img| tm.decode_detailed(img.clone()).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (i, report) in handle.join().unwrap().into_iter().enumerate() {
+                assert_eq!(report.data, watermark);
+                assert_eq!(report.logits, expected[i % expected.len()]);
+            }
+        }
+    }
+
+    fn mock(variant: Variant, version: Version) -> Trustmark {
+        Trustmark::builder(variant, version).build_with_backend(MockBackend)
+    }
+
+    /// The mock of the Q variant with BCH_5 which most tests use, the ghost image, and the
+    /// watermark to encode in it.
+    fn mock_ghost() -> (Trustmark, DynamicImage, String) {
+        (
+            mock(Variant::Q, Version::Bch5),
+            ghost(),
+            WATERMARK.to_owned(),
+        )
+    }
+
+    /// Quantize `img` to 8 bits per channel, as saving it would.
+    fn quantize(img: DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    }
+
+    #[test]
+    fn mock_roundtrip() {
+        let watermark = WATERMARK.to_owned();
+        for path in ["../images/ghost.png", "../images/ufo_240.jpg"] {
+            for variant in Variant::ALL {
+                let tm = mock(variant, Version::Bch5);
+                let input = image::open(path).unwrap();
+                let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+                let report = tm.decode_detailed(quantize(encoded)).unwrap();
+                assert_eq!(report.data, watermark, "{path} {variant}");
+            }
+        }
+    }
+
+    #[test]
+    fn mock_roundtrip_color_types() {
+        let (tm, input, watermark) = mock_ghost();
+        for color in COLOR_TYPES {
+            let input = image_processing::to_color_type(input.clone(), color);
+            let encoded = tm.encode(watermark.clone(), input, 0.95).unwrap();
+            assert_eq!(encoded.color(), color);
+            let removed = tm.remove(encoded.clone(), 1.0).unwrap();
+            assert_eq!(removed.color(), color);
+            assert_eq!(tm.decode(encoded).unwrap(), watermark, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn mock_dither_flat() {
+        let tm = mock(Variant::Q, Version::Bch5);
+        let watermark = WATERMARK.to_owned();
+        // The residual is well under half a level, so rounding leaves flat images as they were.
+        // Levels far from mid-gray bias the mock's decoder, so only those near it are tried.
+        for level in [96, 110, 128, 145, 160] {
+            let input = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                256,
+                256,
+                image::Rgb([level; 3]),
+            ));
+            for (dither, found) in [(Dither::None, false), (Dither::FloydSteinberg, true)] {
+                let options = EncodeOptions {
+                    strength: Some(0.015),
+                    dither,
+                    ..Default::default()
+                };
+                let encoded = tm
+                    .encode_with_options(watermark.clone(), input.clone(), &options)
+                    .unwrap();
+                assert_eq!(encoded.color(), image::ColorType::Rgb8);
+                let decoded = tm.decode(encoded).ok();
+                assert_eq!(
+                    decoded.as_ref() == Some(&watermark),
+                    found,
+                    "{level} {dither:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mock_encode_report() {
+        let (tm, input, watermark) = mock_ghost();
+        let reports = [0.3, 0.95].map(|strength| {
+            let options = EncodeOptions {
+                strength: Some(strength),
+                ..Default::default()
+            };
+            tm.encode_with_report(watermark.clone(), input.clone(), &options)
+                .unwrap()
+        });
+        for report in &reports {
+            assert_eq!(report.psnr, metrics::psnr(&input, &report.image).unwrap());
+            assert!(report.ssim > 0. && report.ssim < 1.);
+        }
+        assert!(reports[0].psnr > reports[1].psnr);
+        assert!(reports[0].ssim > reports[1].ssim);
+    }
+
+    #[test]
+    fn mock_strength_search() {
+        let (tm, input, watermark) = mock_ghost();
+        let encode = |target| {
+            let options = EncodeOptions {
+                strength_search: Some(StrengthSearch::new(target)),
+                ..Default::default()
+            };
+            tm.encode_with_report(watermark.clone(), input.clone(), &options)
+        };
+
+        // The mock's pattern is much stronger than the models' watermarks, so its PSNR is low.
+        let report = encode(StrengthTarget::Psnr(30.)).unwrap();
+        assert!(report.psnr >= 30., "{}", report.psnr);
+        assert!(report.strength > 0.1 && report.strength < 1.0);
+
+        let report = encode(StrengthTarget::Jpeg(80)).unwrap();
+        let compressed = image_processing::jpeg_roundtrip(&report.image, 80).unwrap();
+        assert_eq!(tm.decode(compressed).unwrap(), watermark);
+        let weaker = EncodeOptions {
+            strength: Some(report.strength - 0.05),
+            ..Default::default()
+        };
+        let weaker = tm
+            .encode_with_options(watermark.clone(), input.clone(), &weaker)
+            .unwrap();
+        let compressed = image_processing::jpeg_roundtrip(&weaker, 80).unwrap();
+        assert_ne!(tm.decode(compressed).ok(), Some(watermark.clone()));
+
+        assert!(matches!(
+            encode(StrengthTarget::Psnr(90.)),
+            Err(Error::StrengthTargetUnreachable)
+        ));
+    }
+
+    #[test]
+    fn mock_encode_verified() {
+        let (tm, input, watermark) = mock_ghost();
+        let options = EncodeOptions {
+            strength: Some(0.05),
+            ..Default::default()
+        };
+        let verify = Verify {
+            jpeg_quality: Some(80),
+            step: 0.1,
+            max_strength: 1.0,
+        };
+
+        let report = tm
+            .encode_verified(watermark.clone(), input.clone(), &options, &verify)
+            .unwrap();
+        assert!(report.strength > 0.05 && report.strength <= 1.0);
+        let compressed = image_processing::jpeg_roundtrip(&report.image, 80).unwrap();
+        assert_eq!(tm.decode(compressed).unwrap(), watermark);
+
+        let verify = Verify {
+            max_strength: 0.05,
+            ..verify
+        };
+        assert!(matches!(
+            tm.encode_verified(watermark, input, &options, &verify),
+            Err(Error::Unverified { .. })
+        ));
+    }
+
+    #[test]
+    fn mock_residual_renditions() {
+        let (tm, input, watermark) = mock_ghost();
+        let options = EncodeOptions::default();
+        let residual = tm
+            .compute_residual(watermark.clone(), input.clone(), &options)
+            .unwrap();
+        let residual = Residual::from_bytes(&residual.to_bytes()).unwrap();
+
+        let encoded = tm
+            .encode_with_options(watermark.clone(), input.clone(), &options)
+            .unwrap();
+        assert_eq!(residual.apply(input.clone()).unwrap(), encoded);
+        let report = residual.apply_with_report(input.clone()).unwrap();
+        let expected = tm
+            .encode_with_report(watermark.clone(), input.clone(), &options)
+            .unwrap();
+        assert_eq!(report.image, encoded);
+        assert_eq!(
+            (report.strength, report.psnr, report.clipped),
+            (expected.strength, expected.psnr, expected.clipped)
+        );
+
+        let (width, height) = input.dimensions();
+        for scale in [0.5, 2.] {
+            let rendition = input.resize_exact(
+                (width as f32 * scale) as u32,
+                (height as f32 * scale) as u32,
+                image::imageops::FilterType::Triangle,
+            );
+            let encoded = residual.apply(rendition).unwrap();
+            assert_eq!(tm.decode(encoded).unwrap(), watermark, "{scale}");
+        }
+    }
+
+    #[test]
+    fn mock_residual_layer() {
+        let (tm, input, watermark) = mock_ghost();
+        let rect = Rect {
+            x: 100,
+            y: 50,
+            width: 300,
+            height: 200,
+        };
+        let options = EncodeOptions {
+            region: Some(Region::Rect(rect)),
+            ..Default::default()
+        };
+        let decode_options = DecodeOptions {
+            region: Some(rect),
+            ..Default::default()
+        };
+        let residual = tm
+            .compute_residual(watermark.clone(), input.clone(), &options)
+            .unwrap();
+        let encoded = residual.apply(input.clone()).unwrap().into_rgb8();
+
+        for format in [layer::Format::Float, layer::Format::Biased16] {
+            let layer = residual.layer(input.dimensions(), format).unwrap();
+            let composited = layer::apply(input.clone(), &layer, Dither::None).unwrap();
+            let decoded = tm
+                .decode_with_options(composited.clone(), &decode_options)
+                .unwrap();
+            assert_eq!(decoded.data, watermark);
+            for (composited, encoded) in composited.into_rgb8().iter().zip(encoded.iter()) {
+                assert!(composited.abs_diff(*encoded) <= 1, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn mock_frames() {
+        let (tm, input, watermark) = mock_ghost();
+        let input = input.into_rgb8();
+        let (width, height) = input.dimensions();
+
+        // Rows padded to a multiple of 64 bytes, as video decoders often do.
+        let bgra_stride = (width as usize * 4).next_multiple_of(64) + 64;
+        let mut bgra = vec![0; bgra_stride * height as usize];
+        for (x, y, pixel) in input.enumerate_pixels() {
+            let offset = y as usize * bgra_stride + x as usize * 4;
+            let [r, g, b] = pixel.0;
+            bgra[offset..offset + 4].copy_from_slice(&[b, g, r, 255]);
+        }
+
+        // Limited range BT.601, with the chroma of each 2x2 block taken from its top left.
+        let mut nv12 = vec![0; width as usize * height as usize * 3 / 2];
+        for (x, y, pixel) in input.enumerate_pixels() {
+            let [r, g, b] = pixel.0.map(|value| value as f32 / 255.);
+            let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+            nv12[(y * width + x) as usize] = (16. + 219. * luma).round() as u8;
+            if x % 2 == 0 && y % 2 == 0 {
+                let chroma = ((height + y / 2) * width + x) as usize;
+                nv12[chroma] = (128. + 224. * (b - luma) / 1.772).round() as u8;
+                nv12[chroma + 1] = (128. + 224. * (r - luma) / 1.402).round() as u8;
+            }
+        }
+
+        for (mut data, stride, layout) in [
+            (bgra, bgra_stride, PixelLayout::Bgra8),
+            (nv12, width as usize, PixelLayout::Nv12),
+        ] {
+            let mut frame = FrameMut::new(&mut data, width, height, stride, layout).unwrap();
+            tm.encode_frame(watermark.clone(), &mut frame, &EncodeOptions::default())
+                .unwrap();
+            let frame = Frame::new(&data, width, height, stride, layout).unwrap();
+            let report = tm.decode_frame(&frame, &DecodeOptions::default()).unwrap();
+            assert_eq!(report.data, watermark, "{layout:?}");
+            if layout == PixelLayout::Bgra8 {
+                assert!(data
+                    .chunks(4)
+                    .all(|pixel| pixel[3] == 255 || pixel == [0; 4]));
+            }
+        }
+
+        let mut data = vec![0; width as usize * height as usize * 4];
+        let stride = width as usize * 4;
+        let mut frame =
+            FrameMut::new(&mut data, width, height, stride, PixelLayout::Rgba8).unwrap();
+        let options = EncodeOptions {
+            dither: Dither::FloydSteinberg,
+            ..Default::default()
+        };
+        assert!(matches!(
+            tm.encode_frame(watermark, &mut frame, &options),
+            Err(Error::UnsupportedFrameOptions)
+        ));
+    }
+
+    #[test]
+    fn mock_eval() {
+        let tm = mock(Variant::Q, Version::Bch4);
+        let images = ["../images/ghost.png", "../images/ufo_240.jpg"].map(|path| {
+            image::open(path)
+                .unwrap()
+                .resize(256, 256, image::imageops::FilterType::Triangle)
+        });
+        let attacks = [eval::Attack::None, eval::Attack::Crop(0.5)];
+        let evaluations = eval::evaluate(&tm, images, &attacks, &EncodeOptions::default()).unwrap();
+        assert_eq!(evaluations.len(), 2);
+        let [clean, cropped] = &evaluations[..] else {
+            unreachable!()
+        };
+        assert_eq!((clean.variant, clean.version), (Variant::Q, Version::Bch4));
+        assert_eq!(clean.images, 2);
+        assert_eq!(clean.bit_accuracy, 1.);
+        assert_eq!(clean.success_rate, 1.);
+        assert_eq!(clean.corrected_bits, vec![2]);
+        assert!(cropped.bit_accuracy < 0.9);
+        assert_eq!(cropped.success_rate, 0.);
+    }
+
+    #[test]
+    fn mock_gamut_highlights() {
+        let (tm, input, watermark) = mock_ghost();
+        let input = input.brighten(120);
+        let clipped = [Gamut::Clip, Gamut::Redistribute].map(|gamut| {
+            let options = EncodeOptions {
+                gamut,
+                ..Default::default()
+            };
+            let report = tm
+                .encode_with_report(watermark.clone(), input.clone(), &options)
+                .unwrap();
+            assert_eq!(tm.decode(report.image).unwrap(), watermark, "{gamut:?}");
+            report.clipped
+        });
+        assert!(clipped[0] > 0.1, "{clipped:?}");
+        // Pixels white in every channel have nowhere to put their overflow but their neighbours.
+        assert!(clipped[1] < clipped[0] * 0.75, "{clipped:?}");
+    }
+
+    #[test]
+    fn mock_roundtrip_versions() {
+        let input = ghost();
+        for version in [
+            Version::BchSuper,
+            Version::Bch3,
+            Version::Bch4,
+            Version::Bch5,
+        ] {
+            let tm = mock(Variant::Q, version);
+            let watermark = Payload::Text("mock".to_owned());
+            let encoded = tm.encode(watermark.clone(), input.clone(), 0.95).unwrap();
+            let report = tm.decode_detailed(quantize(encoded)).unwrap();
+            assert_eq!(report.version, version);
+            assert_eq!(report.payload(Mode::Text), watermark);
+        }
+    }
+
+    #[test]
+    fn mock_roundtrip_forced_crop() {
+        let tm = mock(Variant::Q, Version::Bch5);
+        let input = image::open("../images/ufo_240.jpg").unwrap();
+        let watermark = Payload::Bytes(vec![0xca, 0xfe]);
+        let encode_options = EncodeOptions {
+            aspect_ratio_limit: 1.0,
+            ..Default::default()
+        };
+        let encoded = tm
+            .encode_with_options(watermark.clone(), input, &encode_options)
+            .unwrap();
+        let decode_options = DecodeOptions {
+            aspect_ratio_limit: 1.0,
+            ..Default::default()
+        };
+        let report = tm
+            .decode_with_options(quantize(encoded), &decode_options)
+            .unwrap();
+        assert_eq!(
+            report.payload(Mode::Bytes),
+            Payload::Bytes(vec![0xca, 0xfe, 0, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn mock_roundtrip_tiled() {
+        let (tm, input, watermark) = mock_ghost();
+        let input = input.resize_exact(768, 256, image::imageops::FilterType::Triangle);
+        let encode_options = EncodeOptions {
+            tiling: Tiling::Tiled,
+            ..Default::default()
+        };
+        let encoded = quantize(
+            tm.encode_with_options(watermark.clone(), input, &encode_options)
+                .unwrap(),
+        );
+        let decode_options = DecodeOptions {
+            tiling: Tiling::Tiled,
+            ..Default::default()
+        };
+        let report = tm
+            .decode_with_options(encoded.clone(), &decode_options)
+            .unwrap();
+        assert_eq!(report.data, watermark);
+
+        // Crop away the left half of the first tile.
+        let cropped = encoded.crop_imm(128, 0, 640, 256);
+        let report = tm.decode_with_options(cropped, &decode_options).unwrap();
+        assert_eq!(report.data, watermark);
+
+        // An empty image has no tiles, and is rejected instead of being tiled.
+        let empty = DynamicImage::new_rgb8(768, 0);
+        assert!(matches!(
+            tm.encode_with_options(watermark, empty.clone(), &encode_options),
+            Err(Error::ImageProcessing(_))
+        ));
+        assert!(matches!(
+            tm.decode_with_options(empty, &decode_options),
+            Err(Error::ImageProcessing(_))
+        ));
+    }
+
+    #[test]
+    fn mock_tiled_within_limit() {
+        /// The mock backend, recording the size of the last batch it decoded.
+        struct Counting(Arc<AtomicUsize>);
+
+        impl InferenceBackend for Counting {
+            fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                MockBackend.encode(images, bits)
+            }
+
+            fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                self.0.store(images.shape()[0], Ordering::Relaxed);
+                MockBackend.decode(images)
+            }
+
+            fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                MockBackend.remove(images)
+            }
+        }
+
+        let batch_size = Arc::new(AtomicUsize::new(0));
+        let tm = Trustmark::builder(Variant::Q, Version::Bch5)
+            .build_with_backend(Counting(batch_size.clone()));
+        let input = DynamicImage::new_rgb8(384, 256);
+        let options = DecodeOptions {
+            tiling: Tiling::Tiled,
+            ..Default::default()
+        };
+        // An image within the aspect ratio limit is encoded whole, so only it is decoded.
+        let _ = tm.decode_with_options(input, &options);
+        assert_eq!(batch_size.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn mock_search() {
+        let (tm, input, watermark) = mock_ghost();
+        let (width, height) = input.dimensions();
+        let encoded = quantize(tm.encode(watermark.clone(), input, 0.95).unwrap());
+        let options = DecodeOptions {
+            search: Some(Search::default()),
+            ..Default::default()
+        };
+
+        // A watermarked image is found as it is.
+        let report = tm.decode_with_options(encoded.clone(), &options).unwrap();
+        assert_eq!(report.data, watermark);
+        assert_eq!(report.transform, None);
+
+        // Crop to the top left 95%, which is restored by padding it back to its original size. The
+        // mock hides each bit in its own part of the image, so it can't survive larger crops, or
+        // inexact scales.
+        let cropped_size = (width * 19 / 20, height * 19 / 20);
+        let cropped = encoded.crop_imm(0, 0, cropped_size.0, cropped_size.1);
+        assert!(matches!(
+            tm.decode(cropped.clone()),
+            Err(Error::CorruptWatermark)
+        ));
+        let crop_options = DecodeOptions {
+            search: Some(Search {
+                scales: vec![0.95, 20. / 19.],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let report = tm.decode_with_options(cropped, &crop_options).unwrap();
+        assert_eq!(report.data, watermark);
+        let transform = report.transform.unwrap();
+        assert_eq!((transform.x, transform.y), (0, 0));
+        assert_eq!(transform.width, width);
+        assert!(!transform.flipped);
+
+        let flipped = encoded.fliph();
+        assert!(matches!(
+            tm.decode(flipped.clone()),
+            Err(Error::CorruptWatermark)
+        ));
+        let report = tm.decode_with_options(flipped, &options).unwrap();
+        assert_eq!(report.data, watermark);
+        assert!(report.transform.unwrap().flipped);
+    }
+
+    #[test]
+    fn mock_search_region() {
+        let (tm, input, watermark) = mock_ghost();
+        let input = input.resize(256, 256, image::imageops::FilterType::Triangle);
+        let flipped = quantize(tm.encode(watermark.clone(), input, 0.95).unwrap()).fliph();
+        let options = |x, y, side| DecodeOptions {
+            search: Some(Search::default()),
+            region: Some(Rect {
+                x,
+                y,
+                width: side,
+                height: side,
+            }),
+            ..Default::default()
+        };
+
+        // The window is in the pixels of the whole image, not of the region.
+        let mut canvas = DynamicImage::new_rgba8(306, 300);
+        image::imageops::overlay(&mut canvas, &flipped, 30, 24);
+        let report = tm
+            .decode_with_options(canvas, &options(30, 24, 256))
+            .unwrap();
+        assert_eq!(report.data, watermark);
+        let transform = report.transform.unwrap();
+        assert_eq!((transform.x, transform.y, transform.width), (30, 24, 256));
+        assert!(transform.flipped);
+
+        // A frame twice the size of the image, which its proxy scales back down exactly.
+        let mut canvas = DynamicImage::new_rgba8(306, 256);
+        image::imageops::overlay(&mut canvas, &flipped, 30, 0);
+        let canvas = canvas.resize_exact(612, 512, image::imageops::FilterType::Nearest);
+        let data = canvas.into_rgba8().into_raw();
+        let frame = Frame::new(&data, 612, 512, 612 * 4, PixelLayout::Rgba8).unwrap();
+        let report = tm.decode_frame(&frame, &options(60, 0, 512)).unwrap();
+        assert_eq!(report.data, watermark);
+        let transform = report.transform.unwrap();
+        assert_eq!((transform.x, transform.y, transform.width), (60, 0, 512));
+    }
+
+    #[test]
+    fn mock_region_rect() {
+        let (tm, input, watermark) = mock_ghost();
+        let rect = Rect {
+            x: 100,
+            y: 50,
+            width: 300,
+            height: 200,
+        };
+        let encode_options = EncodeOptions {
+            region: Some(Region::Rect(rect)),
+            ..Default::default()
+        };
+        let encoded = quantize(
+            tm.encode_with_options(watermark.clone(), input.clone(), &encode_options)
+                .unwrap(),
+        );
+        assert_eq!(encoded.dimensions(), input.dimensions());
+        let (input, encoded) = (input.to_rgb8(), encoded.to_rgb8());
+        for (x, y, pixel) in encoded.enumerate_pixels() {
+            let inside = (100..400).contains(&x) && (50..250).contains(&y);
+            if !inside {
+                assert_eq!(pixel, input.get_pixel(x, y), "({x}, {y})");
+            }
+        }
+
+        let decode_options = DecodeOptions {
+            region: Some(rect),
+            ..Default::default()
+        };
+        let report = tm
+            .decode_with_options(DynamicImage::ImageRgb8(encoded), &decode_options)
+            .unwrap();
+        assert_eq!(report.data, watermark);
+    }
+
+    #[test]
+    fn mock_region_mask() {
+        let (tm, input, watermark) = mock_ghost();
+        let (width, height) = input.dimensions();
+        // Leave out a logo in the top left corner.
+        let mask = GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([if x < 64 && y < 64 { 0 } else { 255 }])
+        });
+        let encode_options = EncodeOptions {
+            region: Some(Region::Mask(mask)),
+            ..Default::default()
+        };
+        let encoded = quantize(
+            tm.encode_with_options(watermark.clone(), input.clone(), &encode_options)
+                .unwrap(),
+        );
+        let (original, marked) = (input.to_rgb8(), encoded.to_rgb8());
+        for y in 0..60 {
+            for x in 0..60 {
+                assert_eq!(
+                    marked.get_pixel(x, y),
+                    original.get_pixel(x, y),
+                    "({x}, {y})"
+                );
+            }
+        }
+        assert_eq!(tm.decode(encoded).unwrap(), watermark);
+    }
+
+    #[test]
+    fn invalid_region() {
+        let (tm, input, _) = mock_ghost();
+        let options = DecodeOptions {
+            region: Some(Rect {
+                x: 500,
+                y: 0,
+                width: 100,
+                height: 100,
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            tm.decode_with_options(input, &options),
+            Err(Error::InvalidRegion)
+        ));
+    }
+
+    #[test]
+    fn mock_unwatermarked() {
+        let (tm, input, _) = mock_ghost();
+        assert!(matches!(tm.decode(input), Err(Error::CorruptWatermark)));
+    }
+
+    #[test]
+    fn mock_chase_bits() {
+        let tm = mock(Variant::Q, Version::Bch5);
+        let blank = DynamicImage::new_rgb8(256, 256);
+        let options = DecodeOptions {
+            decoding: Decoding::Chase { bits: 40 },
+            ..Default::default()
+        };
+        assert!(matches!(
+            tm.decode_with_options(blank, &options),
+            Err(Error::InvalidChaseBits)
+        ));
+    }
+
+    #[test]
+    fn mock_aspect_ratio_limit() {
+        let tm = mock(Variant::Q, Version::Bch5);
+        let input = DynamicImage::new_rgb8(256, 256);
+        let watermark = WATERMARK;
+        for limit in [f32::NAN, f32::INFINITY, -2., 0., 0.5] {
+            let encode = EncodeOptions {
+                aspect_ratio_limit: limit,
+                ..Default::default()
+            };
+            let decode = DecodeOptions {
+                aspect_ratio_limit: limit,
+                ..Default::default()
+            };
+            let remove = RemoveOptions {
+                aspect_ratio_limit: limit,
+                ..Default::default()
+            };
+            assert!(matches!(
+                tm.encode_with_options(watermark.to_owned(), input.clone(), &encode),
+                Err(Error::InvalidAspectRatioLimit)
+            ));
+            assert!(matches!(
+                tm.decode_with_options(input.clone(), &decode),
+                Err(Error::InvalidAspectRatioLimit)
+            ));
+            assert!(matches!(
+                tm.remove_with_options(input.clone(), &remove),
+                Err(Error::InvalidAspectRatioLimit)
+            ));
+        }
+    }
+
+    #[test]
+    fn mock_remove() {
+        let (tm, input, watermark) = mock_ghost();
+        let encoded = tm.encode(watermark, input, 0.95).unwrap();
+        let removed = tm.remove(encoded.clone(), 1.0).unwrap();
+
+        // The mock remover only weakens the watermark, since its residual is blurred by the
+        // resizing around the model like any other.
+        let mean_confidence = |img| {
+            let report = tm.decode_detailed(img).unwrap();
+            report.logits.iter().map(|logit| logit.abs()).sum::<f32>() / 100.
+        };
+        assert!(mean_confidence(removed) < mean_confidence(encoded) / 2.);
+    }
+
+    #[test]
+    fn remover_output_shape() {
+        /// A remover which returns images of the wrong size.
+        struct Shrinking;
+
+        impl InferenceBackend for Shrinking {
+            fn encode(&self, _: ArrayD<f32>, _: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                Err(Error::EncoderNotLoaded)
+            }
+
+            fn decode(&self, _: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                Err(Error::DecoderNotLoaded)
+            }
+
+            fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                Ok(ArrayD::zeros(&[images.shape()[0], 3, 128, 128][..]))
+            }
+        }
+
+        let tm = Trustmark::builder(Variant::Q, Version::Bch5).build_with_backend(Shrinking);
+        let input = ghost();
+        assert!(matches!(tm.remove(input, 1.0), Err(Error::Backend(_))));
+    }
+
+    #[test]
+    fn short_batch() {
+        /// The mock backend, leaving the last image out of its outputs.
+        struct Short;
+
+        impl InferenceBackend for Short {
+            fn encode(&self, images: ArrayD<f32>, bits: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                let len = images.shape()[0];
+                Ok(MockBackend
+                    .encode(images, bits)?
+                    .slice_axis_move(Axis(0), (..len - 1).into()))
+            }
+
+            fn decode(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                let len = images.shape()[0];
+                Ok(MockBackend
+                    .decode(images)?
+                    .slice_axis_move(Axis(0), (..len - 1).into()))
+            }
+
+            fn remove(&self, images: ArrayD<f32>) -> Result<ArrayD<f32>, Error> {
+                MockBackend.remove(images)
+            }
+        }
+
+        let tm = Trustmark::builder(Variant::Q, Version::Bch5).build_with_backend(Short);
+        let input = ghost();
+        let watermark = WATERMARK.to_owned();
+        for count in [1, 2] {
+            let imgs = vec![input.clone(); count];
+            assert!(matches!(
+                tm.decode_batch(imgs.clone(), &DecodeOptions::default()),
+                Err(Error::Backend(_))
+            ));
+            let batch = imgs.into_iter().map(|img| (watermark.clone(), img));
+            assert!(matches!(
+                tm.encode_batch(batch, &EncodeOptions::default()),
+                Err(Error::Backend(_))
+            ));
+        }
+        assert!(matches!(
+            tm.encode_with_options(watermark, input, &EncodeOptions::default()),
+            Err(Error::Backend(_))
+        ));
+    }
 }
@@ -19,6 +19,9 @@ pub enum Variant {
 }
 
 impl Variant {
+    /// Every model variant.
+    pub const ALL: [Variant; 4] = [Variant::B, Variant::C, Variant::P, Variant::Q];
+
     pub(super) fn encoder_filename(&self) -> String {
         let suffix = match self {
             Variant::B => "B",
@@ -41,6 +44,17 @@ impl Variant {
         format!("decoder_{suffix}.onnx")
     }
 
+    pub(super) fn remover_filename(&self) -> String {
+        let suffix = match self {
+            Variant::B => "B",
+            Variant::C => "C",
+            Variant::P => "P",
+            Variant::Q => "Q",
+        };
+
+        format!("remover_{suffix}.onnx")
+    }
+
     pub(super) fn strength_multiplier(&self) -> f32 {
         match self {
             Variant::P => 1.25,
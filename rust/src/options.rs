@@ -0,0 +1,254 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use image::GrayImage;
+
+use crate::{Error, Search, StrengthSearch};
+
+/// The aspect ratio above which images are center-cropped, as in the Python implementation.
+pub(crate) const DEFAULT_ASPECT_RATIO_LIMIT: f32 = 2.0;
+
+/// The most bits [`Decoding::Chase`] can flip, which costs up to 65536 corrections.
+pub const MAX_CHASE_BITS: u8 = 16;
+
+/// The strength used when none is given to the builder.
+pub(crate) const DEFAULT_STRENGTH: f32 = 0.95;
+
+/// Options controlling how a watermark is encoded.
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    /// A number between 0 and 1 indicating how strong the watermark should be. Defaults to the
+    /// strength the [`Trustmark`] was built with, which is 0.95 unless set with
+    /// [`TrustmarkBuilder::strength`].
+    ///
+    /// [`Trustmark`]: crate::Trustmark
+    /// [`TrustmarkBuilder::strength`]: crate::TrustmarkBuilder::strength
+    pub strength: Option<f32>,
+    /// Images whose aspect ratio (the longer side over the shorter side) is above this limit only
+    /// have a square in their center watermarked. 1.0 always crops, which suits platforms that
+    /// square-crop images. The P variant always crops. Defaults to 2.0, and must be a finite
+    /// number of at least 1.0.
+    ///
+    /// The same limit must be used when decoding.
+    pub aspect_ratio_limit: f32,
+    /// How images above the aspect ratio limit are watermarked. Defaults to
+    /// [`Tiling::Center`].
+    pub tiling: Tiling,
+    /// The part of the image to watermark. Defaults to the whole image.
+    pub region: Option<Region>,
+    /// How the watermarked image is quantized back to an integer color type, such as 8 bits per
+    /// channel. Defaults to [`Dither::None`].
+    pub dither: Dither,
+    /// What happens to the parts of the watermark which would push pixels past black or white.
+    /// Defaults to [`Gamut::Clip`].
+    pub gamut: Gamut,
+    /// Choose the strength of each image to meet a target, instead of using
+    /// [`EncodeOptions::strength`]. Off by default.
+    ///
+    /// The encoder only runs once per image, but the watermark is applied at every strength
+    /// tried, and decoded too for [`StrengthTarget::Jpeg`], which needs the decoder to be loaded.
+    ///
+    /// [`StrengthTarget::Jpeg`]: crate::StrengthTarget::Jpeg
+    pub strength_search: Option<StrengthSearch>,
+}
+
+/// Options controlling how a watermark is decoded.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// How the error correction bits are used to recover the watermark.
+    pub decoding: Decoding,
+    /// Images whose aspect ratio is above this limit only have a square in their center decoded.
+    /// This must match the limit used when encoding. Defaults to 2.0.
+    pub aspect_ratio_limit: f32,
+    /// Where the watermark is looked for in images above the aspect ratio limit. Defaults to
+    /// [`Tiling::Center`].
+    pub tiling: Tiling,
+    /// Search for the watermark in crops, rescalings and flips of the image when it isn't found in
+    /// the image as it is. Off by default.
+    ///
+    /// Candidates are tried in the order of [`Search::scales`], decoding every candidate of a
+    /// scale in one batch, and the first candidate whose watermark passes the error correction
+    /// check is returned. Each candidate makes it more likely that a watermark is found in an
+    /// image which doesn't have one, so candidates are held to [`Search::max_corrected_bits`].
+    pub search: Option<Search>,
+    /// Only decode this rectangle of the image, such as the [`Region::Rect`] the watermark was
+    /// encoded in. Defaults to the whole image.
+    pub region: Option<Rect>,
+}
+
+/// Options controlling how a watermark is removed.
+#[derive(Debug, Clone)]
+pub struct RemoveOptions {
+    /// How strongly the watermark should be removed. 1.0 is a normal strength, and the default.
+    pub strength: f32,
+    /// Images whose aspect ratio is above this limit only have a square in their center cleaned.
+    /// This should match the limit used when encoding. Defaults to 2.0.
+    pub aspect_ratio_limit: f32,
+}
+
+/// A rectangle of an image, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The part of an image to watermark, so that faces, logos or text overlays can be left as they
+/// are.
+#[derive(Debug, Clone)]
+pub enum Region {
+    /// Only watermark this rectangle, which is encoded as if it were the whole image. The rest of
+    /// the image is left as it is.
+    ///
+    /// Decode with the same rectangle as [`DecodeOptions::region`] to decode only this rectangle.
+    Rect(Rect),
+    /// Scale the watermark at each pixel by the mask, from none where it is 0 to all of it where
+    /// it is 255. The mask is stretched to the size of the image.
+    ///
+    /// The whole image is still encoded and decoded, so masking out large parts of the image
+    /// weakens the watermark.
+    Mask(GrayImage),
+}
+
+/// How a watermarked image is quantized back to the integer color type of the input.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Dither {
+    /// Round each channel to the nearest level.
+    ///
+    /// At low strengths, the watermark can be smaller than half a level in flat parts of the
+    /// image, where rounding wipes it out.
+    #[default]
+    None,
+    /// Floyd-Steinberg error diffusion, which carries the rounding error of each pixel over to its
+    /// neighbours, so that the watermark survives on average.
+    FloydSteinberg,
+}
+
+/// How the watermark is applied where it would push pixels past black or white.
+///
+/// [`EncodeReport::clipped`] reports how much of the watermark was lost either way.
+///
+/// [`EncodeReport::clipped`]: crate::EncodeReport::clipped
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Gamut {
+    /// Clamp each channel, losing what doesn't fit, as in the Python implementation. Very bright
+    /// and very dark images lose much of their watermark this way.
+    #[default]
+    Clip,
+    /// Shift the brightness which doesn't fit into the other channels of the pixel, and diffuse
+    /// whatever still doesn't fit into the neighbouring pixels.
+    Redistribute,
+}
+
+/// How images whose aspect ratio is above the limit are watermarked.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Tiling {
+    /// Only watermark the square in the center of the image, as in the Python implementation.
+    #[default]
+    Center,
+    /// Watermark the same payload in as many squares as fit along the longer side of the image,
+    /// so that a crop of a panorama which keeps any one of them is still watermarked.
+    ///
+    /// When decoding, squares overlapping by half their size are decoded across the whole image,
+    /// and their logits are averaged to vote on each bit. If the vote can't be corrected, the
+    /// square needing the fewest corrected bits is used instead. Decoding each square makes it
+    /// more likely that a watermark is found in an image which doesn't have one.
+    Tiled,
+}
+
+/// The strategy used to correct bit flips in a decoded watermark.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Decoding {
+    /// Threshold the decoder output and correct up to [`Version`]'s number of bit flips.
+    ///
+    /// [`Version`]: crate::Version
+    #[default]
+    Hard,
+    /// Soft-decision (Chase) decoding.
+    ///
+    /// When hard-decision decoding fails, every combination of the `bits` least confident bits is
+    /// flipped and corrected in turn, which can recover watermarks with more bit flips than the
+    /// version tolerates. This costs up to `2^bits` corrections, and makes it more likely that a
+    /// watermark is found in an image which doesn't have one, so `bits` should be kept small. 8 is
+    /// a reasonable value, and decoding returns [`Error::InvalidChaseBits`] above
+    /// [`MAX_CHASE_BITS`].
+    Chase { bits: u8 },
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            strength: None,
+            aspect_ratio_limit: DEFAULT_ASPECT_RATIO_LIMIT,
+            tiling: Tiling::default(),
+            region: None,
+            dither: Dither::default(),
+            gamut: Gamut::default(),
+            strength_search: None,
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Check that the options are within their limits.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        validate_aspect_ratio_limit(self.aspect_ratio_limit)
+    }
+}
+
+impl DecodeOptions {
+    /// Check that the options are within their limits.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        validate_aspect_ratio_limit(self.aspect_ratio_limit)?;
+        match self.decoding {
+            Decoding::Chase { bits } if bits > MAX_CHASE_BITS => Err(Error::InvalidChaseBits),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl RemoveOptions {
+    /// Check that the options are within their limits.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        validate_aspect_ratio_limit(self.aspect_ratio_limit)
+    }
+}
+
+/// Check that an aspect ratio limit is a finite number of at least 1, as the options taking one
+/// require.
+///
+/// Returns [`Error::InvalidAspectRatioLimit`] otherwise.
+pub fn validate_aspect_ratio_limit(limit: f32) -> Result<(), Error> {
+    if (1.0..f32::INFINITY).contains(&limit) {
+        Ok(())
+    } else {
+        Err(Error::InvalidAspectRatioLimit)
+    }
+}
+
+impl Default for RemoveOptions {
+    fn default() -> Self {
+        Self {
+            strength: 1.0,
+            aspect_ratio_limit: DEFAULT_ASPECT_RATIO_LIMIT,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            decoding: Decoding::default(),
+            aspect_ratio_limit: DEFAULT_ASPECT_RATIO_LIMIT,
+            tiling: Tiling::default(),
+            search: None,
+            region: None,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use image::DynamicImage;
+
+use crate::{Mode, Payload, Transform, Variant, Version};
+
+/// A watermarked image, with a description of how much it changed and how well the watermark
+/// fit into it.
+#[derive(Debug, Clone)]
+pub struct EncodeReport {
+    /// The watermarked image, as returned by [`Trustmark::encode_with_options`].
+    ///
+    /// [`Trustmark::encode_with_options`]: crate::Trustmark::encode_with_options
+    pub image: DynamicImage,
+    /// The peak signal-to-noise ratio between the input image and the watermarked image, in
+    /// decibels, as computed by [`metrics::psnr`].
+    ///
+    /// [`metrics::psnr`]: crate::metrics::psnr
+    pub psnr: f32,
+    /// The structural similarity between the input image and the watermarked image, as computed
+    /// by [`metrics::ssim`].
+    ///
+    /// [`metrics::ssim`]: crate::metrics::ssim
+    pub ssim: f32,
+    /// The strength the image was watermarked at, which was chosen by the
+    /// [`EncodeOptions::strength_search`] if there was one.
+    ///
+    /// [`EncodeOptions::strength_search`]: crate::EncodeOptions::strength_search
+    pub strength: f32,
+    /// The fraction of the watermark which was lost because it would have pushed pixels past
+    /// black or white, between 0 and 1.
+    ///
+    /// This is usually close to 0, but can be large for very bright or very dark images, which
+    /// then decode poorly. Setting [`EncodeOptions::gamut`] to [`Gamut::Redistribute`] keeps
+    /// more of the watermark in them.
+    ///
+    /// [`EncodeOptions::gamut`]: crate::EncodeOptions::gamut
+    /// [`Gamut::Redistribute`]: crate::Gamut::Redistribute
+    pub clipped: f32,
+}
+
+/// A detailed description of a decoded watermark.
+#[derive(Debug, Clone)]
+pub struct DecodeReport {
+    /// The data bits of the watermark, as returned by [`Trustmark::decode`].
+    ///
+    /// [`Trustmark::decode`]: crate::Trustmark::decode
+    pub data: String,
+    /// The error correction schema the watermark was encoded with.
+    pub version: Version,
+    /// The model variant whose decoder found the watermark.
+    pub variant: Variant,
+    /// The number of bit flips which were corrected by the error correction bits.
+    pub corrected_bits: u8,
+    /// Whether the version identifier in the watermark was corrupt, so that the other versions
+    /// had to be tried.
+    pub version_fallback: bool,
+    /// The raw output of the decoder for each of the 100 watermark bits.
+    ///
+    /// Negative values are read as '0' bits, and all others as '1' bits. The further a value is
+    /// from 0, the more confident the decoder is in that bit.
+    pub logits: Vec<f32>,
+    /// The transform under which a [`DecodeOptions::search`] found the watermark, or `None` if it
+    /// was found in the image as it is.
+    ///
+    /// [`DecodeOptions::search`]: crate::DecodeOptions::search
+    pub transform: Option<Transform>,
+}
+
+impl DecodeReport {
+    /// Interpret the data bits as a [`Payload`] of the given `mode`.
+    pub fn payload(&self, mode: Mode) -> Payload {
+        Payload::from_bitstring(&self.data, mode)
+    }
+
+    /// The confidence of the least confident bit, i.e. the magnitude of the logit closest to 0.
+    pub fn min_confidence(&self) -> f32 {
+        self.logits
+            .iter()
+            .map(|logit| logit.abs())
+            .fold(f32::INFINITY, f32::min)
+    }
+}
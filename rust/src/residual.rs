@@ -0,0 +1,647 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView as _, GrayImage, Rgb, Rgb32FImage,
+};
+use ndarray::{ArrayD, IxDyn};
+
+use crate::{
+    image_processing::{self, ModelImage},
+    layer, options, Dither, EncodeReport, Error, FrameMut, Gamut, Rect, Variant, Watermarked,
+    ENCODE_SIZE,
+};
+
+/// The first bytes of a serialized [`Residual`], followed by the version of the format.
+const MAGIC: &[u8; 4] = b"TMRS";
+const FORMAT_VERSION: u8 = 1;
+
+/// How far the aspect ratio of a rendition may be from that of the image a [`Residual`] was
+/// computed for, as a fraction, when the aspect ratio matters.
+const ASPECT_RATIO_TOLERANCE: f32 = 0.01;
+
+/// The change a watermark makes to an image, computed once by [`Trustmark::compute_residual`]
+/// and applied to any number of renditions of the image without running the encoder again.
+///
+/// The residual is kept at the resolution of the model, and is scaled to each rendition as it is
+/// applied, just as [`Trustmark::encode`] scales it to the input image. Renditions must be resized
+/// copies of the image the residual was computed for. Images whose aspect ratio is under the limit
+/// are squashed into a square for the models, so their renditions may also be stretched to
+/// another aspect ratio under the limit. The residual of an image with a [`Region::Rect`] is
+/// applied to the same part of each rendition.
+///
+/// Residuals can be cached with [`Residual::to_bytes`] and [`Residual::from_bytes`].
+///
+/// [`Trustmark::compute_residual`]: crate::Trustmark::compute_residual
+/// [`Trustmark::encode`]: crate::Trustmark::encode
+/// [`Region::Rect`]: crate::Region::Rect
+#[derive(Debug, Clone, PartialEq)]
+pub struct Residual {
+    /// The variant which computed the residual, which decides how images are center-cropped.
+    pub(crate) variant: Variant,
+    /// The aspect ratio limit the residual was computed with.
+    pub(crate) aspect_ratio_limit: f32,
+    /// The strength the residual was computed at.
+    pub(crate) strength: f32,
+    /// The dimensions of the image the residual was computed for.
+    pub(crate) dimensions: (u32, u32),
+    /// The rectangle of the image which was watermarked, if not the whole image.
+    pub(crate) rect: Option<Rect>,
+    /// The mask which scales the residual at each pixel, if there is one.
+    pub(crate) mask: Option<GrayImage>,
+    /// How to handle parts of the residual which push pixels past black or white.
+    pub(crate) gamut: Gamut,
+    /// How to quantize the watermarked image.
+    pub(crate) dither: Dither,
+    /// The residual itself, as a `[1, 3, height, width]` array in the units of the models.
+    pub(crate) residual: ArrayD<f32>,
+}
+
+impl Residual {
+    /// The strength the residual was computed at.
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    /// The dimensions of the image the residual was computed for.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    /// Watermark `img`, a rendition of the image the residual was computed for.
+    ///
+    /// The watermarked image has the same [`ColorType`](image::ColorType) as `img`. Returns
+    /// [`Error::ResidualMismatch`] if `img` isn't shaped like the image the residual was computed
+    /// for.
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage, Error> {
+        Ok(self.apply_clipped(img)?.0)
+    }
+
+    /// Watermark `img` like [`Residual::apply`], and report how much the image changed and how
+    /// much of the residual was lost to clipping, like [`Trustmark::encode_with_report`].
+    ///
+    /// [`Trustmark::encode_with_report`]: crate::Trustmark::encode_with_report
+    pub fn apply_with_report(&self, img: DynamicImage) -> Result<EncodeReport, Error> {
+        let (image, clipped) = self.apply_clipped(img.clone())?;
+        Watermarked {
+            image,
+            strength: self.strength,
+            clipped,
+        }
+        .report(&img)
+    }
+
+    /// Watermark `img`, also returning the fraction of the residual which was clipped.
+    pub(crate) fn apply_clipped(&self, img: DynamicImage) -> Result<(DynamicImage, f32), Error> {
+        let color = img.color();
+        let dimensions = img.dimensions();
+        let (img, outer) = match &self.rect {
+            Some(rect) => {
+                let rect = scale(rect, self.dimensions, dimensions);
+                (crate::crop(&img, &rect)?, Some((img, rect)))
+            }
+            None => (img, None),
+        };
+        if !self.fits(dimensions, img.dimensions()) {
+            return Err(Error::ResidualMismatch);
+        }
+
+        let (encoded, clipped) =
+            image_processing::apply_residual(img, self.image()?, self.mask.as_ref(), self.gamut);
+        let encoded = match outer {
+            Some((outer, rect)) => image_processing::paste(outer, &encoded, (rect.x, rect.y)),
+            None => encoded,
+        };
+        let image = match self.dither {
+            Dither::None => image_processing::to_color_type(encoded, color),
+            Dither::FloydSteinberg => image_processing::dither(encoded, color),
+        };
+        Ok((image, clipped))
+    }
+
+    /// The residual upscaled to a rendition of the given dimensions, as a [`layer`] of the given
+    /// format.
+    ///
+    /// The layer covers the whole rendition, and is 0 outside the [`Region::Rect`] or where the
+    /// mask is black. Compositing it with [`layer::apply`] watermarks the rendition like
+    /// [`Residual::apply`], as long as the residual was computed with [`Gamut::Clip`], since
+    /// layers are always clipped. Returns [`Error::ResidualMismatch`] if a rendition of these
+    /// dimensions isn't shaped like the image the residual was computed for.
+    ///
+    /// [`Region::Rect`]: crate::Region::Rect
+    pub fn layer(
+        &self,
+        dimensions: (u32, u32),
+        format: layer::Format,
+    ) -> Result<DynamicImage, Error> {
+        let rect = self.target(dimensions)?;
+
+        // The same resampling as `image_processing::apply_residual`, in units of the image.
+        let residual = self
+            .image()?
+            .resize_exact(rect.width, rect.height, FilterType::Triangle)
+            .into_rgb32f();
+        let mask = self
+            .mask
+            .as_ref()
+            .map(|mask| imageops::resize(mask, rect.width, rect.height, FilterType::Triangle));
+        let mut changes = Rgb32FImage::new(dimensions.0, dimensions.1);
+        for (x, y, pixel) in residual.enumerate_pixels() {
+            let weight = mask
+                .as_ref()
+                .map_or(1., |mask| mask.get_pixel(x, y)[0] as f32 / 255.);
+            changes.put_pixel(
+                rect.x + x,
+                rect.y + y,
+                Rgb(pixel.0.map(|value| (value - 0.5) * weight)),
+            );
+        }
+        Ok(layer::encode(changes, format))
+    }
+
+    /// Watermark `frame` in place, like [`Residual::apply`].
+    ///
+    /// The residual is upscaled straight into the frame, without copying it. Each channel is
+    /// clipped and rounded, so [`Error::UnsupportedFrameOptions`] is returned if the residual was
+    /// computed with [`Gamut::Redistribute`] or [`Dither::FloydSteinberg`], which need a copy of
+    /// the frame.
+    pub fn apply_frame(&self, frame: &mut FrameMut<'_>) -> Result<(), Error> {
+        if self.gamut != Gamut::Clip || self.dither != Dither::None {
+            return Err(Error::UnsupportedFrameOptions);
+        }
+        let rect = self.target(frame.dimensions())?;
+        let residual = self.image()?.into_rgb32f();
+        let mask = self.mask.as_ref();
+        frame.apply_changes(&rect, |x, y| {
+            // The same resampling as `image_processing::apply_residual` when upscaling.
+            let source = |(width, height): (u32, u32)| {
+                (source(x, rect.width, width), source(y, rect.height, height))
+            };
+            let (u, v) = source(residual.dimensions());
+            let pixel = imageops::interpolate_bilinear(&residual, u, v).expect("inside residual");
+            let weight = mask.map_or(1., |mask| {
+                let (u, v) = source(mask.dimensions());
+                let pixel = imageops::interpolate_bilinear(mask, u, v).expect("inside mask");
+                pixel[0] as f32 / 255.
+            });
+            pixel.0.map(|value| (value - 0.5) * weight)
+        });
+        Ok(())
+    }
+
+    /// The part of a rendition of the given dimensions which the residual covers.
+    ///
+    /// Returns [`Error::ResidualMismatch`] if a rendition of these dimensions isn't shaped like
+    /// the image the residual was computed for, or the part isn't inside it.
+    fn target(&self, dimensions: (u32, u32)) -> Result<Rect, Error> {
+        let rect = match &self.rect {
+            Some(rect) => scale(rect, self.dimensions, dimensions),
+            None => Rect {
+                x: 0,
+                y: 0,
+                width: dimensions.0,
+                height: dimensions.1,
+            },
+        };
+        if inside(&rect, dimensions) && self.fits(dimensions, (rect.width, rect.height)) {
+            Ok(rect)
+        } else {
+            Err(Error::ResidualMismatch)
+        }
+    }
+
+    /// The residual as an image at the resolution of the model.
+    fn image(&self) -> Result<DynamicImage, Error> {
+        let ModelImage(_, _, _, residual) = (
+            ENCODE_SIZE,
+            self.variant,
+            self.aspect_ratio_limit,
+            self.residual.clone(),
+        )
+            .try_into()?;
+        Ok(residual)
+    }
+
+    /// Whether the residual can be applied to a rendition of the given dimensions, of which the
+    /// part with `encoded` dimensions is watermarked.
+    fn fits(&self, dimensions: (u32, u32), encoded: (u32, u32)) -> bool {
+        let original = match &self.rect {
+            Some(rect) => (rect.width, rect.height),
+            None => self.dimensions,
+        };
+        let center_cropped =
+            image_processing::is_center_cropped(self.variant, original, self.aspect_ratio_limit);
+        if center_cropped
+            != image_processing::is_center_cropped(self.variant, encoded, self.aspect_ratio_limit)
+        {
+            return false;
+        }
+        // The residual of a rectangle only lines up if the rendition isn't stretched.
+        if center_cropped || self.rect.is_some() {
+            let (original, rendition) = match &self.rect {
+                Some(_) => (self.dimensions, dimensions),
+                None => (original, encoded),
+            };
+            let ratio = aspect_ratio(rendition) / aspect_ratio(original);
+            (ratio - 1.).abs() <= ASPECT_RATIO_TOLERANCE
+        } else {
+            true
+        }
+    }
+
+    /// Serialize the residual, to be restored with [`Residual::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend(self.variant.to_string().as_bytes());
+        bytes.extend(self.aspect_ratio_limit.to_le_bytes());
+        bytes.extend(self.strength.to_le_bytes());
+        bytes.extend(self.dimensions.0.to_le_bytes());
+        bytes.extend(self.dimensions.1.to_le_bytes());
+        match &self.rect {
+            Some(rect) => {
+                bytes.push(1);
+                for value in [rect.x, rect.y, rect.width, rect.height] {
+                    bytes.extend(value.to_le_bytes());
+                }
+            }
+            None => bytes.push(0),
+        }
+        match &self.mask {
+            Some(mask) => {
+                bytes.push(1);
+                bytes.extend(mask.width().to_le_bytes());
+                bytes.extend(mask.height().to_le_bytes());
+                bytes.extend(mask.as_raw());
+            }
+            None => bytes.push(0),
+        }
+        bytes.push(match self.gamut {
+            Gamut::Clip => 0,
+            Gamut::Redistribute => 1,
+        });
+        bytes.push(match self.dither {
+            Dither::None => 0,
+            Dither::FloydSteinberg => 1,
+        });
+        let shape = self.residual.shape();
+        bytes.extend((shape[2] as u32).to_le_bytes());
+        bytes.extend((shape[3] as u32).to_le_bytes());
+        for value in self.residual.iter() {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Restore a residual serialized by [`Residual::to_bytes`].
+    ///
+    /// Returns [`Error::InvalidResidual`] if `bytes` are truncated, or describe an empty residual
+    /// or a rectangle outside the image.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC || reader.u8()? != FORMAT_VERSION {
+            return Err(Error::InvalidResidual);
+        }
+        let variant = std::str::from_utf8(reader.take(1)?)
+            .map_err(|_| Error::InvalidResidual)?
+            .parse()
+            .map_err(|_| Error::InvalidResidual)?;
+        let aspect_ratio_limit = reader.f32()?;
+        options::validate_aspect_ratio_limit(aspect_ratio_limit)
+            .map_err(|_| Error::InvalidResidual)?;
+        let strength = reader.f32()?;
+        let dimensions = (reader.u32()?, reader.u32()?);
+        if !strength.is_finite() || dimensions.0 == 0 || dimensions.1 == 0 {
+            return Err(Error::InvalidResidual);
+        }
+        let rect = match reader.u8()? {
+            0 => None,
+            1 => {
+                let rect = Rect {
+                    x: reader.u32()?,
+                    y: reader.u32()?,
+                    width: reader.u32()?,
+                    height: reader.u32()?,
+                };
+                if !inside(&rect, dimensions) {
+                    return Err(Error::InvalidResidual);
+                }
+                Some(rect)
+            }
+            _ => return Err(Error::InvalidResidual),
+        };
+        let mask = match reader.u8()? {
+            0 => None,
+            1 => {
+                let (width, height) = (reader.u32()?, reader.u32()?);
+                let len = reader.len(&[width, height], 1)?;
+                let raw = reader.take(len)?.to_vec();
+                Some(GrayImage::from_raw(width, height, raw).ok_or(Error::InvalidResidual)?)
+            }
+            _ => return Err(Error::InvalidResidual),
+        };
+        let gamut = match reader.u8()? {
+            0 => Gamut::Clip,
+            1 => Gamut::Redistribute,
+            _ => return Err(Error::InvalidResidual),
+        };
+        let dither = match reader.u8()? {
+            0 => Dither::None,
+            1 => Dither::FloydSteinberg,
+            _ => return Err(Error::InvalidResidual),
+        };
+        let (height, width) = (reader.u32()?, reader.u32()?);
+        let len = reader.len(&[3, height, width], 4)?;
+        // The residual is last, so it must be the rest of the bytes.
+        if len != reader.0.len() {
+            return Err(Error::InvalidResidual);
+        }
+        let values = (0..len / 4)
+            .map(|_| reader.f32())
+            .collect::<Result<_, _>>()?;
+        let residual =
+            ArrayD::from_shape_vec(IxDyn(&[1, 3, height as usize, width as usize]), values)
+                .map_err(|_| Error::InvalidResidual)?;
+        Ok(Self {
+            variant,
+            aspect_ratio_limit,
+            strength,
+            dimensions,
+            rect,
+            mask,
+            gamut,
+            dither,
+            residual,
+        })
+    }
+}
+
+/// Whether `rect` is a non-empty part of an image of the given dimensions.
+fn inside(rect: &Rect, (width, height): (u32, u32)) -> bool {
+    rect.width > 0
+        && rect.height > 0
+        && rect
+            .x
+            .checked_add(rect.width)
+            .is_some_and(|right| right <= width)
+        && rect
+            .y
+            .checked_add(rect.height)
+            .is_some_and(|bottom| bottom <= height)
+}
+
+/// Scale `rect` from an image of dimensions `from` to an image of dimensions `to`.
+pub(crate) fn scale(rect: &Rect, from: (u32, u32), to: (u32, u32)) -> Rect {
+    let scale = |value: u32, from: u32, to: u32| {
+        (value as f64 * to as f64 / from.max(1) as f64).round() as u32
+    };
+    let x = scale(rect.x, from.0, to.0);
+    let y = scale(rect.y, from.1, to.1);
+    Rect {
+        x,
+        y,
+        width: scale(rect.x.saturating_add(rect.width), from.0, to.0).saturating_sub(x),
+        height: scale(rect.y.saturating_add(rect.height), from.1, to.1).saturating_sub(y),
+    }
+}
+
+/// The position in a source of `source_length` pixels which pixel `i` of `length` pixels is
+/// sampled from, with the centers of the pixels aligned and clamped to the source.
+fn source(i: u32, length: u32, source_length: u32) -> f32 {
+    let position = (i as f32 + 0.5) * source_length as f32 / length as f32 - 0.5;
+    position.clamp(0., (source_length - 1) as f32)
+}
+
+fn aspect_ratio((width, height): (u32, u32)) -> f32 {
+    width as f32 / height.max(1) as f32
+}
+
+/// Reads the fields of a serialized [`Residual`] in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// The number of bytes in a non-empty array of the given shape, with `size` bytes per value,
+    /// if that many bytes are left.
+    fn len(&self, shape: &[u32], size: usize) -> Result<usize, Error> {
+        shape
+            .iter()
+            .try_fold(size, |len, &dim| len.checked_mul(dim as usize))
+            .filter(|&len| len > 0 && len <= self.0.len())
+            .ok_or(Error::InvalidResidual)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::InvalidResidual);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("four bytes"),
+        ))
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(
+            self.take(4)?.try_into().expect("four bytes"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    fn residual(dimensions: (u32, u32)) -> Residual {
+        Residual {
+            variant: Variant::Q,
+            aspect_ratio_limit: 2.0,
+            strength: 0.95,
+            dimensions,
+            rect: None,
+            mask: None,
+            gamut: Gamut::Clip,
+            dither: Dither::None,
+            residual: ArrayD::from_shape_fn(IxDyn(&[1, 3, 8, 8]), |index| {
+                (index[1] + index[2] + index[3]) as f32 / 100.
+            }),
+        }
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let residual = Residual {
+            rect: Some(Rect {
+                x: 1,
+                y: 2,
+                width: 30,
+                height: 40,
+            }),
+            mask: Some(GrayImage::from_pixel(3, 2, Luma([200]))),
+            gamut: Gamut::Redistribute,
+            dither: Dither::FloydSteinberg,
+            ..residual((64, 48))
+        };
+        assert_eq!(
+            Residual::from_bytes(&residual.to_bytes()).unwrap(),
+            residual
+        );
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = residual((64, 48)).to_bytes();
+        for bytes in [&bytes[..bytes.len() - 1], &bytes[1..], b"TMRS"] {
+            assert!(matches!(
+                Residual::from_bytes(bytes),
+                Err(Error::InvalidResidual)
+            ));
+        }
+
+        // Residuals larger than the bytes left, or empty.
+        let shape = bytes.len() - 3 * 8 * 8 * 4 - 8;
+        for (height, width) in [(u32::MAX, u32::MAX), (1 << 30, 4), (0, 8), (8, 0)] {
+            let mut bytes = bytes.clone();
+            bytes[shape..shape + 4].copy_from_slice(&height.to_le_bytes());
+            bytes[shape + 4..shape + 8].copy_from_slice(&width.to_le_bytes());
+            assert!(matches!(
+                Residual::from_bytes(&bytes),
+                Err(Error::InvalidResidual)
+            ));
+        }
+
+        let rect = |x, width| {
+            Some(Rect {
+                x,
+                y: 0,
+                width,
+                height: 8,
+            })
+        };
+        for residual in [
+            residual((0, 48)),
+            Residual {
+                aspect_ratio_limit: f32::NAN,
+                ..residual((64, 48))
+            },
+            Residual {
+                rect: rect(60, 8),
+                ..residual((64, 48))
+            },
+            Residual {
+                rect: rect(u32::MAX, 2),
+                ..residual((64, 48))
+            },
+            Residual {
+                rect: rect(0, 0),
+                ..residual((64, 48))
+            },
+        ] {
+            assert!(matches!(
+                Residual::from_bytes(&residual.to_bytes()),
+                Err(Error::InvalidResidual)
+            ));
+        }
+    }
+
+    #[test]
+    fn renditions() {
+        let square = residual((64, 48));
+        // Renditions under the aspect ratio limit can be stretched.
+        assert!(square.apply(DynamicImage::new_rgb8(32, 24)).is_ok());
+        assert!(square.apply(DynamicImage::new_rgb8(40, 40)).is_ok());
+        assert!(matches!(
+            square.apply(DynamicImage::new_rgb8(100, 40)),
+            Err(Error::ResidualMismatch)
+        ));
+
+        // The residual of a panorama only covers its center, so must keep its aspect ratio.
+        let panorama = residual((300, 100));
+        assert!(panorama.apply(DynamicImage::new_rgb8(150, 50)).is_ok());
+        assert!(matches!(
+            panorama.apply(DynamicImage::new_rgb8(150, 60)),
+            Err(Error::ResidualMismatch)
+        ));
+    }
+
+    #[test]
+    fn rect_outside_rendition() {
+        let residual = Residual {
+            rect: Some(Rect {
+                x: 6,
+                y: 0,
+                width: 6,
+                height: 8,
+            }),
+            ..residual((8, 8))
+        };
+        let mut data = [0; 8 * 8 * 4];
+        let mut frame = FrameMut::new(&mut data, 8, 8, 32, crate::PixelLayout::Rgba8).unwrap();
+        assert!(matches!(
+            residual.apply_frame(&mut frame),
+            Err(Error::ResidualMismatch)
+        ));
+        assert!(matches!(
+            residual.layer((8, 8), layer::Format::Float),
+            Err(Error::ResidualMismatch)
+        ));
+    }
+
+    #[test]
+    fn unsupported_frame_options() {
+        let mut data = [0; 8 * 8 * 4];
+        let mut frame = FrameMut::new(&mut data, 8, 8, 32, crate::PixelLayout::Rgba8).unwrap();
+        for residual in [
+            Residual {
+                gamut: Gamut::Redistribute,
+                ..residual((8, 8))
+            },
+            Residual {
+                dither: Dither::FloydSteinberg,
+                ..residual((8, 8))
+            },
+        ] {
+            assert!(matches!(
+                residual.apply_frame(&mut frame),
+                Err(Error::UnsupportedFrameOptions)
+            ));
+        }
+        assert!(data.iter().all(|&value| value == 0));
+    }
+
+    #[test]
+    fn scale_rect() {
+        let rect = Rect {
+            x: 10,
+            y: 20,
+            width: 30,
+            height: 40,
+        };
+        let scaled = scale(&rect, (100, 100), (50, 25));
+        assert_eq!(
+            scaled,
+            Rect {
+                x: 5,
+                y: 5,
+                width: 15,
+                height: 10,
+            }
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use image::{imageops, DynamicImage, GenericImageView as _, Rgba, Rgba32FImage};
+
+/// The candidate transforms tried by a decode search, set with [`DecodeOptions::search`].
+///
+/// Every candidate is a window of the image with the same aspect ratio as the image, which is
+/// decoded as if it were the whole image. A window smaller than the image is a crop, which finds
+/// watermarked images within screenshots. A window larger than the image is padded with gray,
+/// which finds the watermark in crops of a watermarked image, by restoring the scale it was
+/// encoded at.
+///
+/// [`DecodeOptions::search`]: crate::DecodeOptions::search
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    /// The sizes of the windows, as a fraction of the image's size, in the order they are tried.
+    ///
+    /// Sizes below 1 crop the image, and sizes above 1 pad it.
+    pub scales: Vec<f32>,
+    /// The number of positions along each side of the image at which windows of each scale are
+    /// tried, spread evenly from one edge to the other. A single position centers the window.
+    pub positions: u32,
+    /// Whether to also try each window flipped horizontally, as social media sometimes mirrors
+    /// images.
+    pub flips: bool,
+    /// The most bit flips a candidate's watermark may need corrected to be accepted.
+    ///
+    /// With many candidates, some are likely to decode to noise which the error correction can
+    /// correct into a valid watermark, particularly with the weaker versions. So candidates are
+    /// held to a stricter standard than the image itself: their version identifier must be
+    /// intact, and they may only need a few bits corrected.
+    pub max_corrected_bits: u8,
+}
+
+/// A transform of the image under which a decode search found the watermark, as reported in
+/// [`DecodeReport::transform`].
+///
+/// [`DecodeReport::transform`]: crate::DecodeReport::transform
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    /// The horizontal offset of the decoded window in the image, even when only a
+    /// [`DecodeOptions::region`] of it is searched. Negative if the window extends past the left
+    /// edge.
+    ///
+    /// [`DecodeOptions::region`]: crate::DecodeOptions::region
+    pub x: i32,
+    /// The vertical offset of the decoded window in the image. Negative if the window extends
+    /// past the top edge.
+    pub y: i32,
+    /// The width of the decoded window.
+    pub width: u32,
+    /// The height of the decoded window.
+    pub height: u32,
+    /// Whether the window was flipped horizontally.
+    pub flipped: bool,
+}
+
+impl Default for Search {
+    /// Crops down to 60% and pads up to 150% of the image, at 3x3 positions, with flips, and
+    /// accepts at most 1 corrected bit. This is up to 73 runs of the decoder.
+    fn default() -> Self {
+        Self {
+            scales: vec![1.0, 0.8, 1.25, 0.6, 1.5],
+            positions: 3,
+            flips: true,
+            max_corrected_bits: 1,
+        }
+    }
+}
+
+impl Search {
+    /// The candidate transforms for an image of the given size, grouped by scale.
+    ///
+    /// The image itself is left out, since it is decoded before searching.
+    pub(crate) fn candidates(&self, (width, height): (u32, u32)) -> Vec<Vec<Transform>> {
+        self.scales
+            .iter()
+            .map(|&scale| {
+                let window_width = ((width as f32 * scale).round() as u32).max(1);
+                let window_height = ((height as f32 * scale).round() as u32).max(1);
+                let mut transforms = Vec::new();
+                for y in offsets(height, window_height, self.positions) {
+                    for x in offsets(width, window_width, self.positions) {
+                        for flipped in [false, true] {
+                            let transform = Transform {
+                                x,
+                                y,
+                                width: window_width,
+                                height: window_height,
+                                flipped,
+                            };
+                            if (self.flips || !flipped)
+                                && !transform.is_identity((width, height))
+                                && !transforms.contains(&transform)
+                            {
+                                transforms.push(transform);
+                            }
+                        }
+                    }
+                }
+                transforms
+            })
+            .collect()
+    }
+}
+
+/// The offsets of `positions` windows of length `window` spread evenly along `length`.
+fn offsets(length: u32, window: u32, positions: u32) -> impl Iterator<Item = i32> {
+    let slack = length as i32 - window as i32;
+    let positions = positions.max(1) as i32;
+    (0..positions).map(move |i| {
+        if positions == 1 {
+            slack / 2
+        } else {
+            i * slack / (positions - 1)
+        }
+    })
+}
+
+impl Transform {
+    /// Whether this transform leaves an image of the given size as it is.
+    fn is_identity(&self, (width, height): (u32, u32)) -> bool {
+        *self
+            == Transform {
+                x: 0,
+                y: 0,
+                width,
+                height,
+                flipped: false,
+            }
+    }
+
+    /// The size of the window relative to the image it was taken from.
+    pub fn scale(&self, (width, _): (u32, u32)) -> f32 {
+        self.width as f32 / width as f32
+    }
+
+    /// Take the window out of `img`.
+    pub(crate) fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let inside = self.x >= 0
+            && self.y >= 0
+            && self.x as u32 + self.width <= width
+            && self.y as u32 + self.height <= height;
+        let window = if inside {
+            img.crop_imm(self.x as u32, self.y as u32, self.width, self.height)
+        } else {
+            let mut canvas =
+                Rgba32FImage::from_pixel(self.width, self.height, Rgba([0.5, 0.5, 0.5, 1.0]));
+            imageops::overlay(
+                &mut canvas,
+                &img.to_rgba32f(),
+                -self.x as i64,
+                -self.y as i64,
+            );
+            canvas.into()
+        };
+        if self.flipped {
+            window.fliph()
+        } else {
+            window
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn flipped_identity_first() {
+        let candidates = Search::default().candidates((100, 50));
+        assert_eq!(candidates.len(), 5);
+        assert_eq!(
+            candidates[0],
+            vec![Transform {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 50,
+                flipped: true,
+            }]
+        );
+        assert_eq!(candidates[1].len(), 18);
+    }
+
+    #[test]
+    fn crop_positions() {
+        let search = Search {
+            scales: vec![0.5],
+            positions: 3,
+            flips: false,
+            max_corrected_bits: 1,
+        };
+        let offsets: Vec<_> = search.candidates((100, 40))[0]
+            .iter()
+            .map(|transform| (transform.x, transform.y))
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                (0, 0),
+                (25, 0),
+                (50, 0),
+                (0, 10),
+                (25, 10),
+                (50, 10),
+                (0, 20),
+                (25, 20),
+                (50, 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn pad_positions() {
+        let search = Search {
+            scales: vec![1.5],
+            positions: 1,
+            flips: false,
+            max_corrected_bits: 1,
+        };
+        let transform = search.candidates((100, 40))[0][0];
+        assert_eq!((transform.x, transform.y), (-25, -10));
+        assert_eq!(transform.scale((100, 40)), 1.5);
+    }
+
+    #[test]
+    fn apply_pad_and_flip() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0])));
+        let transform = Transform {
+            x: -4,
+            y: 0,
+            width: 8,
+            height: 2,
+            flipped: true,
+        };
+        let window = transform.apply(&img).to_rgb8();
+        assert_eq!(window.dimensions(), (8, 2));
+        // The image was padded on its left, which is the right once flipped.
+        assert_eq!(window.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(window.get_pixel(7, 0).0, [128, 128, 128]);
+    }
+}
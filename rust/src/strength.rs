@@ -0,0 +1,218 @@
+// Copyright 2025 Adobe
+// All Rights Reserved.
+//
+// NOTICE: Adobe permits you to use, modify, and distribute this file in
+// accordance with the terms of the Adobe license agreement accompanying
+// it.
+
+use crate::Error;
+
+/// The most times [`Trustmark::encode_verified`] raises the strength, each of which encodes and
+/// decodes the image once more.
+///
+/// [`Trustmark::encode_verified`]: crate::Trustmark::encode_verified
+pub const MAX_VERIFY_STEPS: usize = 64;
+
+/// A goal for the strength of each image to meet, as set in [`StrengthSearch::target`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StrengthTarget {
+    /// Watermark as strongly as possible while keeping at least this PSNR, in decibels, as
+    /// computed by [`metrics::psnr`].
+    ///
+    /// [`metrics::psnr`]: crate::metrics::psnr
+    Psnr(f32),
+    /// Watermark as weakly as possible while the watermark still decodes after the image is
+    /// compressed as a JPEG of this quality, from 1 to 100.
+    Jpeg(u8),
+}
+
+/// A search for the strength which meets a target, set with [`EncodeOptions::strength_search`].
+///
+/// PSNR falls as the strength rises, and robustness rises with it, so the search bisects the
+/// range of strengths for the strength where the target stops being met. Strengths are before the
+/// variant's own multiplier, which the P variant uses to watermark more strongly, so they compare
+/// with the strengths passed to [`Trustmark::encode`].
+///
+/// [`EncodeOptions::strength_search`]: crate::EncodeOptions::strength_search
+/// [`Trustmark::encode`]: crate::Trustmark::encode
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrengthSearch {
+    /// The goal to meet.
+    pub target: StrengthTarget,
+    /// The weakest strength to try.
+    pub min_strength: f32,
+    /// The strongest strength to try.
+    pub max_strength: f32,
+    /// How many times the range of strengths is halved after trying both ends of it. Each step
+    /// applies the watermark once more.
+    pub steps: u32,
+}
+
+impl StrengthSearch {
+    /// Search strengths between 0.1 and 1.0 in 6 steps, which finds the strength to within 0.015.
+    pub fn new(target: StrengthTarget) -> Self {
+        Self {
+            target,
+            min_strength: 0.1,
+            max_strength: 1.0,
+            steps: 6,
+        }
+    }
+
+    /// Find the strength closest to where the target stops being met.
+    ///
+    /// `meets` watermarks at the given strength, and returns the result if it meets the target.
+    /// The result at the best strength found is returned, or an error if even the most favorable
+    /// end of the range misses the target.
+    pub(crate) fn run<T>(
+        &self,
+        mut meets: impl FnMut(f32) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
+        let (mut met, mut missed) = match self.target {
+            StrengthTarget::Psnr(_) => (self.min_strength, self.max_strength),
+            StrengthTarget::Jpeg(_) => (self.max_strength, self.min_strength),
+        };
+        let Some(mut best) = meets(met)? else {
+            return Err(Error::StrengthTargetUnreachable);
+        };
+        if let Some(result) = meets(missed)? {
+            return Ok(result);
+        }
+        for _ in 0..self.steps {
+            let strength = (met + missed) / 2.;
+            match meets(strength)? {
+                Some(result) => {
+                    met = strength;
+                    best = result;
+                }
+                None => missed = strength,
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// How [`Trustmark::encode_verified`] checks that a watermark decodes.
+///
+/// [`Trustmark::encode_verified`]: crate::Trustmark::encode_verified
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Verify {
+    /// Compress the watermarked image as a JPEG of this quality, from 1 to 100, before decoding
+    /// it. If `None`, the watermarked image is decoded as it is.
+    pub jpeg_quality: Option<u8>,
+    /// How much the strength is raised each time the watermark doesn't decode. Must be positive,
+    /// and large enough to reach the maximum in at most [`MAX_VERIFY_STEPS`] steps.
+    pub step: f32,
+    /// The strongest strength to try, which is always tried last even if the steps skip over it.
+    pub max_strength: f32,
+}
+
+impl Verify {
+    /// The strengths to try in turn, from `first` up to [`Verify::max_strength`].
+    ///
+    /// Returns [`Error::InvalidVerify`] unless the step is positive, every strength is finite and
+    /// there are at most [`MAX_VERIFY_STEPS`] steps.
+    pub(crate) fn strengths(&self, first: f32) -> Result<Vec<f32>, Error> {
+        let finite = [self.step, self.max_strength, first]
+            .iter()
+            .all(|x| x.is_finite());
+        if !finite || self.step <= 0. {
+            return Err(Error::InvalidVerify);
+        }
+        if first > self.max_strength {
+            return Ok(Vec::new());
+        }
+        let steps = ((self.max_strength - first) / self.step).ceil();
+        if steps > MAX_VERIFY_STEPS as f32 {
+            return Err(Error::InvalidVerify);
+        }
+        let steps = steps as usize;
+        Ok((0..steps)
+            .map(|i| first + i as f32 * self.step)
+            .filter(|&strength| strength < self.max_strength)
+            .chain(std::iter::once(self.max_strength))
+            .collect())
+    }
+}
+
+impl Default for Verify {
+    /// Decode the image as it is, raising the strength by 0.25 up to 2.0.
+    fn default() -> Self {
+        Self {
+            jpeg_quality: None,
+            step: 0.25,
+            max_strength: 2.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_strengths() {
+        let verify = Verify::default();
+        assert_eq!(
+            verify.strengths(0.95).unwrap(),
+            [0.95, 1.2, 1.45, 1.7, 1.95, 2.0]
+        );
+        assert_eq!(verify.strengths(1.5).unwrap(), [1.5, 1.75, 2.0]);
+        assert_eq!(verify.strengths(2.0).unwrap(), [2.0]);
+        assert!(verify.strengths(2.5).unwrap().is_empty());
+        for step in [0., -0.25, f32::NAN, f32::INFINITY, 1e-9, f32::MIN_POSITIVE] {
+            let verify = Verify {
+                step,
+                ..Default::default()
+            };
+            assert!(matches!(verify.strengths(0.95), Err(Error::InvalidVerify)));
+        }
+        let verify = Verify {
+            step: 0.5,
+            max_strength: 0.5 + MAX_VERIFY_STEPS as f32 * 0.5,
+            ..Default::default()
+        };
+        assert_eq!(verify.strengths(0.5).unwrap().len(), MAX_VERIFY_STEPS + 1);
+        assert!(matches!(verify.strengths(0.), Err(Error::InvalidVerify)));
+    }
+
+    #[test]
+    fn highest_strength_under_psnr() {
+        let search = StrengthSearch::new(StrengthTarget::Psnr(40.));
+        // A stand-in for the PSNR, which falls as the strength rises.
+        let found = search
+            .run(|strength| Ok((strength <= 0.42).then_some(strength)))
+            .unwrap();
+        assert!((0.42 - 0.015..=0.42).contains(&found), "{found}");
+    }
+
+    #[test]
+    fn lowest_strength_surviving_jpeg() {
+        let search = StrengthSearch::new(StrengthTarget::Jpeg(75));
+        let mut tries = 0;
+        let found = search
+            .run(|strength| {
+                tries += 1;
+                Ok((strength >= 0.7).then_some(strength))
+            })
+            .unwrap();
+        assert!((0.7..0.7 + 0.015).contains(&found), "{found}");
+        assert_eq!(tries, 8);
+    }
+
+    #[test]
+    fn whole_range_meets_target() {
+        let search = StrengthSearch::new(StrengthTarget::Jpeg(75));
+        let found = search.run(|strength| Ok(Some(strength))).unwrap();
+        assert_eq!(found, 0.1);
+    }
+
+    #[test]
+    fn unreachable_target() {
+        let search = StrengthSearch::new(StrengthTarget::Psnr(60.));
+        assert!(matches!(
+            search.run(|_| Ok(None::<f32>)),
+            Err(Error::StrengthTargetUnreachable)
+        ));
+    }
+}